@@ -0,0 +1,547 @@
+//! Allocators that serve memory from a region inside of an address space
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{
+    clip,
+    lock::{Guard, Lock},
+    stats::{BlockState, Counters, HeapStats},
+};
+use crate::{
+    ptr::{MutPtr, NonNull},
+    space::{AddressSpace, NullEncoding},
+};
+
+struct FreeBlock<S: AddressSpace> {
+    next: Option<NonNull<FreeBlock<S>, S>>,
+    size: usize,
+}
+
+/// An address-ordered first-fit free list over a region
+///
+/// The region isn't stored in the list, it is passed in by the allocator so that pointers are
+/// always derived from the allocator's current location.
+struct FreeList<S: AddressSpace> {
+    head: Option<NonNull<FreeBlock<S>, S>>,
+    /// The managed part of the region, as offsets from its start
+    range: (usize, usize),
+    /// Offset of the unit containing the null offset, if it is in the managed part
+    null: Option<usize>,
+    counters: Counters,
+}
+
+impl<S: AddressSpace> FreeList<S> {
+    /// The granularity of allocations, large enough to hold a free block header
+    const UNIT: usize = {
+        let size = core::mem::size_of::<FreeBlock<S>>();
+        let align = core::mem::align_of::<FreeBlock<S>>();
+        let unit = if size > align { size } else { align };
+        let unit = if unit > S::GRANULE { unit } else { S::GRANULE };
+        unit.next_power_of_two()
+    };
+
+    const fn empty() -> Self {
+        Self {
+            head: None,
+            range: (0, 0),
+            null: None,
+            counters: Counters::new(),
+        }
+    }
+
+    /// Writes a free block and returns the link to it
+    ///
+    /// # Safety
+    /// The block has to be inside of the region and not overlap any allocation.
+    unsafe fn write_block(
+        region: *mut u8,
+        addr: usize,
+        size: usize,
+        next: Option<NonNull<FreeBlock<S>, S>>,
+    ) -> Option<NonNull<FreeBlock<S>, S>> {
+        let block = region.with_addr(addr).cast::<FreeBlock<S>>();
+        block.write(FreeBlock { next, size });
+        NonNull::new(MutPtr::new(block)?)
+    }
+
+    /// Builds the free list from the part of the region that can be encoded in the address space
+    ///
+    /// # Safety
+    /// The region has to be valid for writes of `len` bytes and not be used by anything else.
+    unsafe fn init(&mut self, region: *mut u8, len: usize) {
+        let lo = region.addr().next_multiple_of(Self::UNIT);
+        let hi = (region.addr() + len) & !(Self::UNIT - 1);
+        let Some((lo, hi)) = clip::encodable::<S>(lo, hi, Self::UNIT) else {
+            return;
+        };
+        // The unit containing the null offset is never handed out
+        let null = S::Null::null()
+            .map(|null| S::decode(null) & !(Self::UNIT - 1))
+            .filter(|null| (lo..hi).contains(null));
+        self.range = (lo - region.addr(), hi - region.addr());
+        self.null = null.map(|null| null - region.addr());
+        self.head = match null {
+            Some(null) => {
+                let upper = if null + Self::UNIT < hi {
+                    Self::write_block(region, null + Self::UNIT, hi - null - Self::UNIT, None)
+                } else {
+                    None
+                };
+                if null > lo {
+                    Self::write_block(region, lo, null - lo, upper)
+                } else {
+                    upper
+                }
+            }
+            None => Self::write_block(region, lo, hi - lo, None),
+        };
+    }
+
+    fn size_of(layout: Layout) -> usize {
+        layout.size().max(1).next_multiple_of(Self::UNIT)
+    }
+
+    /// # Safety
+    /// The region has to be the one that the list was initialized with.
+    unsafe fn allocate(&mut self, region: *mut u8, layout: Layout) -> Option<*mut u8> {
+        let size = Self::size_of(layout);
+        let align = layout.align().max(Self::UNIT);
+        let mut prev: *mut Option<NonNull<FreeBlock<S>, S>> = &mut self.head;
+        // SAFE: all blocks in the free list are inside of the region and not allocated
+        unsafe {
+            while let Some(link) = *prev {
+                let block = link.as_ptr().as_wide_ptr();
+                let FreeBlock { next, size: len } = block.read();
+                let addr = block.addr();
+                let start = addr.next_multiple_of(align);
+                if start + size <= addr + len {
+                    let end = start + size;
+                    let back = if end < addr + len {
+                        Self::write_block(region, end, addr + len - end, next)
+                    } else {
+                        next
+                    };
+                    if start > addr {
+                        Self::write_block(region, addr, start - addr, back);
+                    } else {
+                        *prev = back;
+                    }
+                    self.counters.allocate(size);
+                    return Some(region.with_addr(start));
+                }
+                prev = &mut (*block).next;
+            }
+        }
+        None
+    }
+
+    /// # Safety
+    /// The pointer has to be allocated from this free list with the same layout.
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let addr = ptr.addr();
+        let mut size = Self::size_of(layout);
+        self.counters.deallocate(size);
+        let mut prev: *mut Option<NonNull<FreeBlock<S>, S>> = &mut self.head;
+        let mut prev_block: *mut FreeBlock<S> = core::ptr::null_mut();
+        while let Some(link) = *prev {
+            let block = link.as_ptr().as_wide_ptr();
+            if block.addr() > addr {
+                break;
+            }
+            prev_block = block;
+            prev = &mut (*block).next;
+        }
+        let mut next = *prev;
+        if let Some(link) = next {
+            let block = link.as_ptr().as_wide_ptr();
+            if addr + size == block.addr() {
+                size += (*block).size;
+                next = (*block).next;
+            }
+        }
+        if !prev_block.is_null() && prev_block.addr() + (*prev_block).size == addr {
+            (*prev_block).size += size;
+            (*prev_block).next = next;
+        } else {
+            *prev = Self::write_block(ptr, addr, size, next);
+        }
+    }
+
+    fn blocks(&self, region: *mut u8) -> Blocks<'_, S> {
+        Blocks {
+            region,
+            pos: region.addr() + self.range.0,
+            end: region.addr() + self.range.1,
+            null: self.null.map(|null| region.addr() + null),
+            next_free: self.head,
+            _list: PhantomData,
+        }
+    }
+
+    fn stats(&self) -> HeapStats {
+        let (mut free, mut largest_free) = (0, 0);
+        let mut link = self.head;
+        while let Some(block) = link {
+            // SAFE: all blocks in the free list are inside of the region and not allocated
+            let block = unsafe { block.as_ptr().as_wide_ptr().read() };
+            free += block.size;
+            largest_free = largest_free.max(block.size);
+            link = block.next;
+        }
+        self.counters.snapshot(free, largest_free)
+    }
+}
+
+/// A first-fit allocator managing a `[u8; SIZE]` region
+///
+/// The region is the arena itself, so it is meant to be the static that the address space is
+/// declared on:
+///
+/// ```
+/// # use tinyptr::{boxed::Box, arena::Arena};
+/// static HEAP: Arena<Heap, 4096> = Arena::new();
+/// tinyptr::address_space!(pub Heap = static HEAP);
+///
+/// let b: Box<u32, _, Heap> = Box::new_in(42, &HEAP);
+/// ```
+///
+/// Every allocation can be encoded in `S`, parts of the region that are outside of the address
+/// space or that contain its null offset are never handed out. Free blocks are kept in an
+/// address-ordered list of tiny pointers and merged on deallocation.
+#[repr(C)]
+pub struct Arena<S: AddressSpace, const SIZE: usize> {
+    region: UnsafeCell<[u8; SIZE]>,
+    list: UnsafeCell<FreeList<S>>,
+    initialized: AtomicBool,
+    lock: Lock,
+}
+
+// SAFE: the free list is only accessed while holding the lock
+unsafe impl<S: AddressSpace, const SIZE: usize> Sync for Arena<S, SIZE> {}
+unsafe impl<S: AddressSpace, const SIZE: usize> Send for Arena<S, SIZE> {}
+
+impl<S: AddressSpace, const SIZE: usize> Arena<S, SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            region: UnsafeCell::new([0; SIZE]),
+            list: UnsafeCell::new(FreeList::empty()),
+            initialized: AtomicBool::new(false),
+            lock: Lock::new(),
+        }
+    }
+
+    /// Locks the allocator, initializing it on first use
+    fn lock(&self) -> Guard<'_> {
+        let guard = self.lock.lock();
+        // The flag is only accessed while the lock is held
+        if !self.initialized.load(Ordering::Relaxed) {
+            self.initialized.store(true, Ordering::Relaxed);
+            // SAFE: the lock is held, and the region is only used by the free list
+            unsafe { (*self.list.get()).init(self.region.get().cast(), SIZE) };
+        }
+        guard
+    }
+
+    /// Calls `f` with an iterator over all blocks, in address order
+    ///
+    /// The allocator is locked while `f` runs, so allocating from it in `f` deadlocks, or panics
+    /// with the `critical-section` feature.
+    pub fn blocks<R>(&self, f: impl FnOnce(Blocks<'_, S>) -> R) -> R {
+        let _guard = self.lock();
+        // SAFE: the lock is held until the iterator is gone
+        f(unsafe { (*self.list.get()).blocks(self.region.get().cast()) })
+    }
+
+    /// Returns a snapshot of the allocator statistics
+    pub fn stats(&self) -> HeapStats {
+        let _guard = self.lock();
+        // SAFE: the lock is held
+        unsafe { (*self.list.get()).stats() }
+    }
+}
+
+impl<S: AddressSpace, const SIZE: usize> Default for Arena<S, SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<S: AddressSpace, const SIZE: usize> Allocator for &Arena<S, SIZE> {
+    fn allocate(&self, layout: Layout) -> Result<core::ptr::NonNull<[u8]>, AllocError> {
+        let _guard = self.lock();
+        // SAFE: the lock is held
+        let ptr = unsafe { (*self.list.get()).allocate(self.region.get().cast(), layout) };
+        let ptr = core::ptr::NonNull::new(ptr.ok_or(AllocError)?).ok_or(AllocError)?;
+        Ok(core::ptr::NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: core::ptr::NonNull<u8>, layout: Layout) {
+        let _guard = self.lock();
+        (*self.list.get()).deallocate(ptr.as_ptr(), layout);
+    }
+}
+
+/// A global allocator that serves memory from a region inside of an address space
+///
+/// This makes `alloc`'s collections and [`Box`](crate::boxed::Box)es using `Global` allocate
+/// memory that can be encoded in `S`:
+///
+/// ```no_run
+/// # use tinyptr::arena::GlobalArena;
+/// tinyptr::address_space!(Heap = runtime);
+///
+/// #[global_allocator]
+/// static ALLOC: GlobalArena<Heap> = GlobalArena::new();
+///
+/// static mut REGION: [u8; 4096] = [0; 4096];
+///
+/// // SAFE: the region is only used by the allocator
+/// let region = unsafe { &mut *&raw mut REGION };
+/// Heap::init(region.as_mut_ptr());
+/// ALLOC.init(region);
+/// ```
+///
+/// Allocations fail until the allocator is initialized. A runtime address space has to be
+/// initialized before that, so that the region can be encoded in it.
+pub struct GlobalArena<S: AddressSpace> {
+    list: UnsafeCell<FreeList<S>>,
+    region: UnsafeCell<*mut u8>,
+    initialized: AtomicBool,
+    lock: Lock,
+}
+
+// SAFE: the free list is only accessed while holding the lock
+unsafe impl<S: AddressSpace> Sync for GlobalArena<S> {}
+unsafe impl<S: AddressSpace> Send for GlobalArena<S> {}
+
+impl<S: AddressSpace> GlobalArena<S> {
+    pub const fn new() -> Self {
+        Self {
+            list: UnsafeCell::new(FreeList::empty()),
+            region: UnsafeCell::new(core::ptr::null_mut()),
+            initialized: AtomicBool::new(false),
+            lock: Lock::new(),
+        }
+    }
+
+    /// Sets the region that memory is allocated from.
+    ///
+    /// Only the part of the region that can be encoded in `S` is used.
+    ///
+    /// # Panics
+    /// This function panics if the allocator has already been initialized.
+    pub fn init(&self, region: &'static mut [u8]) {
+        assert!(self.try_init(region), "allocator initialized twice");
+    }
+
+    /// Sets the region that memory is allocated from, returning false if it has already been
+    /// set.
+    pub fn try_init(&self, region: &'static mut [u8]) -> bool {
+        let _guard = self.lock.lock();
+        if self.initialized.load(Ordering::Relaxed) {
+            return false;
+        }
+        self.initialized.store(true, Ordering::Relaxed);
+        // SAFE: the lock is held and the region is borrowed forever
+        let (ptr, len) = (region.as_mut_ptr(), region.len());
+        unsafe {
+            *self.region.get() = ptr;
+            (*self.list.get()).init(ptr, len);
+        }
+        true
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.initialized.load(Ordering::Relaxed)
+    }
+
+    /// Calls `f` with an iterator over all blocks, in address order, see [`Arena::blocks`]
+    pub fn blocks<R>(&self, f: impl FnOnce(Blocks<'_, S>) -> R) -> R {
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held until the iterator is gone
+        f(unsafe { (*self.list.get()).blocks(*self.region.get()) })
+    }
+
+    /// Returns a snapshot of the allocator statistics
+    pub fn stats(&self) -> HeapStats {
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held
+        unsafe { (*self.list.get()).stats() }
+    }
+}
+
+impl<S: AddressSpace> Default for GlobalArena<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<S: AddressSpace> GlobalAlloc for GlobalArena<S> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _guard = self.lock.lock();
+        (*self.list.get())
+            .allocate(*self.region.get(), layout)
+            .unwrap_or(core::ptr::null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _guard = self.lock.lock();
+        (*self.list.get()).deallocate(ptr, layout);
+    }
+}
+
+/// An iterator over the blocks of an [`Arena`] or [`GlobalArena`], see [`Arena::blocks`]
+///
+/// It yields the start, size and state of every block. The arena doesn't keep headers for
+/// allocations, so a used block is a run of allocated memory between two free blocks, which can
+/// hold several allocations. The unit at the null offset is skipped.
+pub struct Blocks<'a, S: AddressSpace> {
+    region: *mut u8,
+    pos: usize,
+    end: usize,
+    null: Option<usize>,
+    next_free: Option<NonNull<FreeBlock<S>, S>>,
+    _list: PhantomData<&'a FreeList<S>>,
+}
+
+impl<S: AddressSpace> Iterator for Blocks<'_, S> {
+    type Item = (NonNull<u8, S>, Layout, BlockState);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.null == Some(self.pos) {
+            self.pos += FreeList::<S>::UNIT;
+        }
+        if self.pos >= self.end {
+            return None;
+        }
+        let start = self.pos;
+        let free = self.next_free.map(|link| link.as_ptr().as_wide_ptr());
+        let state = match free {
+            Some(block) if block.addr() == start => {
+                // SAFE: the lock is held, and the block is in the free list
+                let FreeBlock { next, size } = unsafe { block.read() };
+                self.next_free = next;
+                self.pos += size;
+                BlockState::Free
+            }
+            _ => {
+                let mut end = free.map_or(self.end, |block| block.addr());
+                if let Some(null) = self.null.filter(|&null| null > start) {
+                    end = end.min(null);
+                }
+                self.pos = end;
+                BlockState::Used
+            }
+        };
+        let ptr = NonNull::new(MutPtr::new(self.region.with_addr(start))?)?;
+        // SAFE: blocks are multiples of the unit, which is a power of two
+        let layout =
+            unsafe { Layout::from_size_align_unchecked(self.pos - start, FreeList::<S>::UNIT) };
+        Some((ptr, layout, state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::{Allocator, GlobalAlloc, Layout};
+
+    use super::{Arena, GlobalArena};
+    use crate::{boxed::Box, space::AddressSpace, stats::BlockState};
+
+    #[test]
+    fn allocate_and_merge() {
+        static HEAP: Arena<Heap, 1024> = Arena::new();
+        crate::address_space!(Heap: u16 = static HEAP);
+
+        let free = HEAP.stats().free;
+        let a: Box<[u8; 40], _, Heap> = Box::new_in([1; 40], &HEAP);
+        let b: Box<[u8; 40], _, Heap> = Box::new_in([2; 40], &HEAP);
+        let c: Box<[u8; 40], _, Heap> = Box::new_in([3; 40], &HEAP);
+        // The blocks are rounded up to the 16 byte unit
+        assert_eq!(HEAP.stats().in_use, 144);
+        drop(b);
+        let states: Vec<_> = HEAP.blocks(|blocks| blocks.map(|(_, _, state)| state).collect());
+        assert_eq!(
+            states,
+            [
+                BlockState::Used,
+                BlockState::Free,
+                BlockState::Used,
+                BlockState::Free
+            ]
+        );
+        assert!(HEAP.stats().fragmentation() > 0);
+        assert_eq!((a[0], c[0]), (1, 3));
+        drop((a, c));
+        let stats = HEAP.stats();
+        assert_eq!(
+            (stats.free, stats.largest_free, stats.in_use),
+            (free, free, 0)
+        );
+        assert_eq!(stats.peak, 144);
+    }
+
+    #[test]
+    fn reuses_freed_blocks() {
+        static HEAP: Arena<Heap, 256> = Arena::new();
+        crate::address_space!(Heap: u8 = static HEAP);
+
+        let layout = Layout::new::<[u8; 64]>();
+        let a = (&HEAP).allocate(layout).unwrap();
+        let b = (&HEAP).allocate(layout).unwrap();
+        // SAFE: the block was allocated with the same layout
+        unsafe { (&HEAP).deallocate(a.cast(), layout) };
+        assert_eq!((&HEAP).allocate(layout).unwrap(), a);
+        assert!((&HEAP).allocate(Layout::new::<[u8; 200]>()).is_err());
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn clips_to_the_address_space() {
+        static HEAP: Arena<Heap, 1024> = Arena::new();
+        crate::address_space!(Heap: u8 = static HEAP);
+
+        let stats = HEAP.stats();
+        assert!(stats.free < 256);
+        HEAP.blocks(|mut blocks| {
+            assert!(blocks.all(|(ptr, layout, _)| ptr.addr() as usize + layout.size() <= 256));
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "critical-section")]
+    #[should_panic(expected = "allocator locked while it is already locked")]
+    fn allocating_during_a_walk() {
+        static HEAP: Arena<Heap, 256> = Arena::new();
+        crate::address_space!(Heap: u8 = static HEAP);
+
+        let _ = HEAP.blocks(|_| (&HEAP).allocate(Layout::new::<u8>()));
+    }
+
+    #[test]
+    fn global() {
+        crate::host_space!(Heap: u16 = 1024);
+        static ALLOC: GlobalArena<Heap> = GlobalArena::new();
+
+        let layout = Layout::new::<u64>();
+        // SAFE: the layout is not zero sized
+        assert!(unsafe { ALLOC.alloc(layout) }.is_null());
+        // SAFE: the buffer of the host arena is not used for anything else
+        let region = unsafe { core::slice::from_raw_parts_mut(Heap::root(), 1024) };
+        ALLOC.init(region);
+        assert!(!ALLOC.try_init(&mut []));
+        unsafe {
+            let ptr = ALLOC.alloc(layout);
+            assert!(!ptr.is_null());
+            ptr.cast::<u64>().write(5);
+            assert_eq!(ALLOC.stats().in_use, 16);
+            ALLOC.dealloc(ptr, layout);
+        }
+        assert_eq!(ALLOC.stats().in_use, 0);
+        assert_eq!(ALLOC.blocks(|blocks| blocks.count()), 1);
+    }
+}
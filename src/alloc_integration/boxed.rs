@@ -1,7 +1,8 @@
 use core::{
     alloc::{AllocError, Allocator, Layout},
     any::Any,
-    mem::MaybeUninit,
+    marker::Unsize,
+    mem::{ManuallyDrop, MaybeUninit},
     ops::{Deref, DerefMut},
     pin::Pin,
     ptr::Pointee,
@@ -10,76 +11,139 @@ use core::{
 use alloc::alloc::Global;
 
 use crate::{
-    ptr::{MutPtr, Unique},
-    util::IntoTiny,
-    Ref, RefMut,
+    ptr::{MutPtr, NonNull, OwningPointer, Unique},
+    space::AddressSpace,
+    util::{IntoTiny, VTableEncoding},
 };
 
-pub struct Box<T, A, const BASE_ADDR: usize>(Unique<T, BASE_ADDR>, A)
+/// An owned allocation in an address space
+///
+/// The pointer to the allocation is stored as `P`, which can be any [`OwningPointer`]. With a
+/// [`PackedSlicePtr`](crate::ptr::PackedSlicePtr), boxed slices can be longer than the length
+/// type of the address space, their raw pointer is then only available through
+/// [`into_pointer_with_allocator`](Box::into_pointer_with_allocator).
+pub struct Box<T, A, S: AddressSpace, P = Unique<T, S>>(P, A)
 where
     T: ?Sized,
     A: Allocator,
-    <T as Pointee>::Metadata: IntoTiny;
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    P: OwningPointer<Target = T, Space = S>;
 
-impl<T, A, const BASE_ADDR: usize> Box<T, A, BASE_ADDR>
+impl<T, A, S: AddressSpace, P> Box<T, A, S, P>
 where
     T: ?Sized,
     A: Allocator,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    P: OwningPointer<Target = T, Space = S>,
 {
-    pub fn into_raw_with_allocator(b: Self) -> (MutPtr<T, BASE_ADDR>, A) {
+    pub fn into_raw_with_allocator(b: Self) -> (MutPtr<T, S>, A) {
+        let (ptr, alloc) = Self::into_pointer_with_allocator(b);
+        (ptr.as_mut_ptr(), alloc)
+    }
+    /// Consumes the box, returning its owning pointer and allocator, see
+    /// [`from_pointer_in`](Box::from_pointer_in)
+    pub fn into_pointer_with_allocator(b: Self) -> (P, A) {
+        let b = ManuallyDrop::new(b);
         let alloc = unsafe { core::ptr::read(&b.1) };
-        (b.0.as_ptr(), alloc)
+        (b.0, alloc)
     }
-    pub fn into_raw(b: Self) -> MutPtr<T, BASE_ADDR> {
+    pub fn into_raw(b: Self) -> MutPtr<T, S> {
         Self::into_raw_with_allocator(b).0
     }
 
-    /// Creates a new Box from raw data
+    /// Creates a new Box from an owning pointer
     ///
     /// # Safety
     /// See alloc's box safety docs
-    pub unsafe fn from_raw_in(ptr: MutPtr<T, BASE_ADDR>, allocator: A) -> Self {
-        Self(Unique::new_unchecked(ptr), allocator)
+    #[cfg_attr(feature = "validate", track_caller)]
+    pub unsafe fn from_pointer_in(ptr: P, allocator: A) -> Self {
+        // The metadata is stored by `P`, which doesn't have to compress it like the address space
+        #[cfg(feature = "validate")]
+        crate::validate::check_pointee::<T, S>(ptr.as_wide_ptr());
+        Self(ptr, allocator)
+    }
+
+    pub fn as_mut_ptr(&mut self) -> MutPtr<T, S> {
+        self.0.as_mut_ptr()
     }
 
+    /// Converts to a box of an unsized type, for example `Box<Foo>` to `Box<dyn Trait>`
+    ///
+    /// Returns the box unchanged if the new metadata can't be compressed, see
+    /// [`MutPtr::unsize`].
+    pub fn unsize<U>(b: Self) -> Result<Box<U, A, S>, Self>
+    where
+        T: Unsize<U>,
+        U: ?Sized,
+        <U as Pointee>::Metadata: IntoTiny<S>,
+    {
+        match b.0.as_mut_ptr().unsize() {
+            Some(ptr) => {
+                let (_, alloc) = Box::into_raw_with_allocator(b);
+                // SAFE: the pointer points to the same allocation
+                Ok(unsafe { Box::from_raw_in(ptr, alloc) })
+            }
+            None => Err(b),
+        }
+    }
+}
+
+impl<T, A, S: AddressSpace> Box<T, A, S>
+where
+    T: ?Sized,
+    A: Allocator,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
     /// Creates a new Box from raw data
     ///
     /// # Safety
     /// See alloc's box safety docs
-    pub unsafe fn from_raw(ptr: MutPtr<T, BASE_ADDR>) -> Box<T, Global, BASE_ADDR> {
-        Box::from_raw_in(ptr, Global)
+    #[cfg_attr(feature = "validate", track_caller)]
+    pub unsafe fn from_raw_in(ptr: MutPtr<T, S>, allocator: A) -> Self {
+        #[cfg(feature = "validate")]
+        crate::validate::check_ptr::<T, S>(ptr.as_wide_ptr());
+        Self(Unique::from_nonnull(NonNull::new_unchecked(ptr)), allocator)
     }
+}
 
-    pub fn as_mut_ptr(&mut self) -> MutPtr<T, BASE_ADDR> {
-        self.0.as_ptr()
+impl<T, S: AddressSpace> Box<T, Global, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    /// Creates a new Box from raw data
+    ///
+    /// # Safety
+    /// See alloc's box safety docs
+    pub unsafe fn from_raw(ptr: MutPtr<T, S>) -> Self {
+        Box::from_raw_in(ptr, Global)
     }
 }
 
-impl<T, A, const BASE_ADDR: usize> Box<T, A, BASE_ADDR>
+impl<T, A, S: AddressSpace> Box<T, A, S>
 where
     A: Allocator,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
-    pub fn try_new_uninit_in(alloc: A) -> Result<Box<MaybeUninit<T>, A, BASE_ADDR>, AllocError> {
+    pub fn try_new_uninit_in(alloc: A) -> Result<Box<MaybeUninit<T>, A, S>, AllocError> {
         let layout = Layout::new::<MaybeUninit<T>>();
-        let ptr = alloc.allocate(layout)?.cast();
-        unsafe {
-            Ok(Box::from_raw_in(
-                MutPtr::new(ptr.as_ptr()).ok_or(AllocError)?,
-                alloc,
-            ))
-        }
-    }
-    pub fn try_new_zeroed_in(alloc: A) -> Result<Box<MaybeUninit<T>, A, BASE_ADDR>, AllocError> {
+        let ptr = alloc.allocate(layout)?.cast::<MaybeUninit<T>>();
+        let Some(tiny) = MutPtr::new(ptr.as_ptr()) else {
+            // SAFE: the memory was just allocated with this layout
+            unsafe { alloc.deallocate(ptr.cast(), layout) };
+            return Err(AllocError);
+        };
+        unsafe { Ok(Box::from_raw_in(tiny, alloc)) }
+    }
+    pub fn try_new_zeroed_in(alloc: A) -> Result<Box<MaybeUninit<T>, A, S>, AllocError> {
         let layout = Layout::new::<MaybeUninit<T>>();
-        let ptr = alloc.allocate_zeroed(layout)?.cast();
-        unsafe {
-            Ok(Box::from_raw_in(
-                MutPtr::new(ptr.as_ptr()).ok_or(AllocError)?,
-                alloc,
-            ))
-        }
+        let ptr = alloc.allocate_zeroed(layout)?.cast::<MaybeUninit<T>>();
+        let Some(tiny) = MutPtr::new(ptr.as_ptr()) else {
+            // SAFE: the memory was just allocated with this layout
+            unsafe { alloc.deallocate(ptr.cast(), layout) };
+            return Err(AllocError);
+        };
+        unsafe { Ok(Box::from_raw_in(tiny, alloc)) }
     }
 
     pub fn try_new_in(x: T, alloc: A) -> Result<Self, AllocError> {
@@ -90,11 +154,11 @@ where
         }
     }
 
-    pub fn new_uninit_in(alloc: A) -> Box<MaybeUninit<T>, A, BASE_ADDR> {
+    pub fn new_uninit_in(alloc: A) -> Box<MaybeUninit<T>, A, S> {
         Self::try_new_uninit_in(alloc).expect("Out of Memory")
     }
 
-    pub fn new_zeroed_in(alloc: A) -> Box<MaybeUninit<T>, A, BASE_ADDR> {
+    pub fn new_zeroed_in(alloc: A) -> Box<MaybeUninit<T>, A, S> {
         Self::try_new_zeroed_in(alloc).expect("Out of Memory")
     }
 
@@ -102,25 +166,25 @@ where
         Self::try_new_in(x, alloc).expect("Out of Memory")
     }
 
-    pub fn try_new_uninit() -> Result<Box<MaybeUninit<T>, Global, BASE_ADDR>, AllocError> {
+    pub fn try_new_uninit() -> Result<Box<MaybeUninit<T>, Global, S>, AllocError> {
         Box::try_new_uninit_in(Global)
     }
 
-    pub fn try_new_zeroed() -> Result<Box<MaybeUninit<T>, Global, BASE_ADDR>, AllocError> {
+    pub fn try_new_zeroed() -> Result<Box<MaybeUninit<T>, Global, S>, AllocError> {
         Box::try_new_zeroed_in(Global)
     }
 
-    pub fn try_new(x: T) -> Result<Box<T, Global, BASE_ADDR>, AllocError> {
+    pub fn try_new(x: T) -> Result<Box<T, Global, S>, AllocError> {
         Box::try_new_in(x, Global)
     }
 
-    pub fn new_uninit() -> Box<MaybeUninit<T>, Global, BASE_ADDR> {
+    pub fn new_uninit() -> Box<MaybeUninit<T>, Global, S> {
         Box::new_uninit_in(Global)
     }
-    pub fn new_zeroed() -> Box<MaybeUninit<T>, Global, BASE_ADDR> {
+    pub fn new_zeroed() -> Box<MaybeUninit<T>, Global, S> {
         Box::new_zeroed_in(Global)
     }
-    pub fn new(x: T) -> Box<T, Global, BASE_ADDR> {
+    pub fn new(x: T) -> Box<T, Global, S> {
         Box::new_in(x, Global)
     }
 
@@ -145,15 +209,15 @@ where
         Box::into_pin(Self::new_in(x, alloc))
     }
 
-    pub fn try_pin(x: T) -> Result<Pin<Box<T, Global, BASE_ADDR>>, AllocError> {
+    pub fn try_pin(x: T) -> Result<Pin<Box<T, Global, S>>, AllocError> {
         Box::try_pin_in(x, Global)
     }
 
-    pub fn pin(x: T) -> Pin<Box<T, Global, BASE_ADDR>> {
+    pub fn pin(x: T) -> Pin<Box<T, Global, S>> {
         Box::pin_in(x, Global)
     }
 
-    pub fn into_boxed_slice(boxed: Self) -> Box<[T], A, BASE_ADDR> {
+    pub fn into_boxed_slice(boxed: Self) -> Box<[T], A, S> {
         let (ptr, alloc) = Box::into_raw_with_allocator(boxed);
         unsafe {
             let ptr = MutPtr::new_unchecked(ptr.as_wide_ptr() as *mut [T; 1] as *mut [T]);
@@ -164,7 +228,7 @@ where
     pub fn into_inner(boxed: Self) -> T {
         let (ptr, alloc) = Box::into_raw_with_allocator(boxed);
         unsafe {
-            let boxed = Box::from_raw_in(ptr.cast::<MaybeUninit<T>>(), alloc);
+            let boxed: Box<MaybeUninit<T>, A, S> = Box::from_raw_in(ptr.cast(), alloc);
             let ptr = ptr.read();
             core::mem::drop(boxed);
             ptr
@@ -172,86 +236,90 @@ where
     }
 }
 
-impl<T, A, const BASE_ADDR: usize> Box<MaybeUninit<T>, A, BASE_ADDR>
+impl<T, A, S: AddressSpace> Box<MaybeUninit<T>, A, S>
 where
     A: Allocator,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
-    /// Converts to `Box<T, A, BASE_ADDR>`
+    /// Converts to `Box<T, A, S>`
     ///
     /// # Safety
     /// The caller has to ensure that the data is validly initialized
-    pub unsafe fn assume_init(self) -> Box<T, A, BASE_ADDR> {
+    pub unsafe fn assume_init(self) -> Box<T, A, S> {
         let (raw, alloc) = Box::into_raw_with_allocator(self);
         Box::from_raw_in(raw.cast(), alloc)
     }
 }
 
-impl<T, A, const BASE_ADDR: usize> Box<[MaybeUninit<T>], A, BASE_ADDR>
+impl<T, A, S: AddressSpace> Box<[MaybeUninit<T>], A, S>
 where
     A: Allocator,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
-    /// Converts to `Box<T, A, BASE_ADDR>`
+    /// Converts to `Box<T, A, S>`
     ///
     /// # Safety
     /// The caller has to ensure that the data is validly initialized
-    pub unsafe fn assume_init(self) -> Box<[T], A, BASE_ADDR> {
+    pub unsafe fn assume_init(self) -> Box<[T], A, S> {
         let (raw, alloc) = Box::into_raw_with_allocator(self);
         let (ptr, metadata) = raw.as_raw_parts();
         Box::from_raw_in(MutPtr::from_raw_parts(ptr, metadata), alloc)
     }
 }
 
-impl<T, A, const BASE_ADDR: usize> Drop for Box<T, A, BASE_ADDR>
+impl<T, A, S: AddressSpace, P> Drop for Box<T, A, S, P>
 where
     T: ?Sized,
     A: Allocator,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    P: OwningPointer<Target = T, Space = S>,
 {
     fn drop(&mut self) {
         unsafe {
-            let layout = Layout::for_value(&*self);
-            self.0.as_ptr().as_wide_ptr().drop_in_place();
+            let layout = Layout::for_value::<T>(&**self);
+            self.0.as_wide_ptr().drop_in_place();
             self.1.deallocate(
-                core::ptr::NonNull::new_unchecked(self.0.as_ptr().as_wide_ptr().cast::<u8>()),
+                core::ptr::NonNull::new_unchecked(self.0.as_wide_ptr().cast::<u8>()),
                 layout,
             );
         }
     }
 }
 
-impl<T, A, const BASE_ADDR: usize> Deref for Box<T, A, BASE_ADDR>
+impl<T, A, S: AddressSpace, P> Deref for Box<T, A, S, P>
 where
     T: ?Sized,
     A: Allocator,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    P: OwningPointer<Target = T, Space = S>,
 {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        unsafe { Ref::as_std_ref(self.0.as_ptr().as_ref_unchecked()) }
+        unsafe { &*self.0.as_wide_ptr() }
     }
 }
 
-impl<T, A, const BASE_ADDR: usize> DerefMut for Box<T, A, BASE_ADDR>
+impl<T, A, S: AddressSpace, P> DerefMut for Box<T, A, S, P>
 where
     T: ?Sized,
     A: Allocator,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    P: OwningPointer<Target = T, Space = S>,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { RefMut::as_std_mut(self.0.as_ptr().as_mut_unchecked()) }
+        unsafe { &mut *self.0.as_wide_ptr() }
     }
 }
 
-impl<A, const BASE_ADDR: usize> Box<dyn Any, A, BASE_ADDR>
+impl<A, S: AddressSpace> Box<dyn Any, A, S>
 where
     A: Allocator,
+    S::VTables: VTableEncoding<dyn Any>,
 {
-    pub fn downcast<T: Any>(self) -> Result<Box<T, A, BASE_ADDR>, Self>
+    pub fn downcast<T: Any>(self) -> Result<Box<T, A, S>, Self>
     where
-        <T as Pointee>::Metadata: IntoTiny,
+        <T as Pointee>::Metadata: IntoTiny<S>,
     {
         if self.is::<T>() {
             unsafe {
@@ -264,13 +332,14 @@ where
     }
 }
 
-impl<A, const BASE_ADDR: usize> Box<dyn Any + Send, A, BASE_ADDR>
+impl<A, S: AddressSpace> Box<dyn Any + Send, A, S>
 where
     A: Allocator,
+    S::VTables: VTableEncoding<dyn Any + Send>,
 {
-    pub fn downcast<T: Any>(self) -> Result<Box<T, A, BASE_ADDR>, Self>
+    pub fn downcast<T: Any>(self) -> Result<Box<T, A, S>, Self>
     where
-        <T as Pointee>::Metadata: IntoTiny,
+        <T as Pointee>::Metadata: IntoTiny<S>,
     {
         if self.is::<T>() {
             unsafe {
@@ -283,13 +352,14 @@ where
     }
 }
 
-impl<A, const BASE_ADDR: usize> Box<dyn Any + Send + Sync, A, BASE_ADDR>
+impl<A, S: AddressSpace> Box<dyn Any + Send + Sync, A, S>
 where
     A: Allocator,
+    S::VTables: VTableEncoding<dyn Any + Send + Sync>,
 {
-    pub fn downcast<T: Any>(self) -> Result<Box<T, A, BASE_ADDR>, Self>
+    pub fn downcast<T: Any>(self) -> Result<Box<T, A, S>, Self>
     where
-        <T as Pointee>::Metadata: IntoTiny,
+        <T as Pointee>::Metadata: IntoTiny<S>,
     {
         if self.is::<T>() {
             unsafe {
@@ -302,6 +372,168 @@ where
     }
 }
 
-pub fn test(test: Box<u32, Global, 0x8000_0000>) -> u32 {
-    Box::into_inner(test)
+#[cfg(test)]
+mod tests {
+    use core::any::Any;
+
+    use super::Box;
+    use crate::{
+        ptr::{MutPtr, NonNull, NonNullPointer, OwningPointer, TinyPointer},
+        space::AddressSpace,
+    };
+
+    crate::vtable_index! {
+        VTables {
+            dyn Any: u8 = [u32, [u8; 3]],
+            dyn AsRef<[u8]>: u8 = [[u8; 3]],
+        }
+    }
+
+    #[test]
+    fn new_and_drop() {
+        crate::host_space!(Heap: u16 = 256);
+        let mut b: Box<u32, _, Heap> = Box::new_in(1, Heap::arena());
+        *b += 1;
+        assert_eq!(*b, 2);
+        assert_eq!(Heap::arena().live(), 1);
+        drop(b);
+        assert_eq!(Heap::arena().live(), 0);
+        assert!(Heap::arena().reset());
+    }
+
+    #[test]
+    fn into_inner_and_raw() {
+        crate::host_space!(Heap: u16 = 256);
+        let b: Box<[u8; 4], _, Heap> = Box::new_in([1, 2, 3, 4], Heap::arena());
+        let (raw, alloc) = Box::into_raw_with_allocator(b);
+        assert!(!raw.is_null());
+        // SAFE: the pointer was returned by into_raw_with_allocator with the same allocator
+        let b = unsafe { Box::from_raw_in(raw, alloc) };
+        assert_eq!(Box::into_inner(b), [1, 2, 3, 4]);
+        assert_eq!(Heap::arena().live(), 0);
+    }
+
+    #[test]
+    fn zero_sized() {
+        crate::host_space!(Heap: u8 = 16);
+        let b: Box<(), _, Heap> = Box::new_in((), Heap::arena());
+        assert_eq!(Box::into_inner(b), ());
+    }
+
+    #[test]
+    fn out_of_memory() {
+        crate::host_space!(Heap: u8 = 16);
+        assert!(Box::<[u8; 32], _, Heap>::try_new_in([0; 32], Heap::arena()).is_err());
+        assert_eq!(Heap::arena().live(), 0);
+    }
+
+    #[test]
+    fn pointer_round_trip() {
+        crate::host_space!(Heap: u16 = 256);
+        let b: Box<u8, _, Heap> = Box::new_in(3, Heap::arena());
+        let raw: MutPtr<u8, Heap> = Box::into_raw(b);
+        // SAFE: the box was allocated from this arena
+        let b = unsafe { Box::from_raw_in(raw, Heap::arena()) };
+        assert_eq!(*b, 3);
+    }
+
+    #[test]
+    fn slices() {
+        crate::host_space!(Heap: u16 = 256);
+        let b: Box<[u16; 3], _, Heap> = Box::new_in([1, 2, 3], Heap::arena());
+        let s: Box<[u16], _, Heap> = Box::unsize(b).ok().unwrap();
+        assert_eq!(&*s, &[1, 2, 3]);
+        let one = Box::into_boxed_slice(Box::<u16, _, Heap>::new_in(7, Heap::arena()));
+        assert_eq!(&*one, &[7]);
+    }
+
+    #[test]
+    fn shifted() {
+        crate::host_space!(Heap: u8 >> 2 = 1024);
+        let a: Box<[u32; 128], _, Heap> = Box::new_in([1; 128], Heap::arena());
+        let b: Box<u32, _, Heap> = Box::new_in(2, Heap::arena());
+        assert!(Heap::arena().used() > 512);
+        assert_eq!((a[127], *b), (1, 2));
+    }
+
+    // Miri doesn't deduplicate vtables, so they can't be found in the index
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn downcast() {
+        crate::host_space!(Heap: u16 = 256, vtables VTables);
+        let b: Box<u32, _, Heap> = Box::new_in(5, Heap::arena());
+        let b: Box<dyn Any, _, Heap> = Box::unsize(b).ok().unwrap();
+        let b = b.downcast::<[u8; 3]>().err().unwrap();
+        assert_eq!(*b.downcast::<u32>().ok().unwrap(), 5);
+        assert_eq!(Heap::arena().live(), 0);
+
+        let b: Box<[u8; 3], _, Heap> = Box::new_in([1, 2, 3], Heap::arena());
+        let b: Box<dyn AsRef<[u8]>, _, Heap> = Box::unsize(b).ok().unwrap();
+        assert_eq!((*b).as_ref(), &[1, 2, 3]);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn missing_vtable() {
+        crate::host_space!(Heap: u16 = 256, vtables VTables);
+        let b: Box<u64, _, Heap> = Box::new_in(5, Heap::arena());
+        let b = Box::unsize::<dyn Any>(b).err().unwrap();
+        assert_eq!(*b, 5);
+    }
+
+    /// An owning pointer other than `Unique`
+    struct Owned<T, S: AddressSpace>(NonNull<T, S>);
+
+    impl<T, S: AddressSpace> Copy for Owned<T, S> {}
+
+    impl<T, S: AddressSpace> Clone for Owned<T, S> {
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<T, S: AddressSpace> TinyPointer for Owned<T, S> {
+        type Target = T;
+        type Space = S;
+        type Metadata = ();
+        type Cast<U> = NonNull<U, S>;
+
+        fn as_mut_ptr(self) -> MutPtr<T, S> {
+            self.0.as_ptr()
+        }
+
+        fn cast<U>(self) -> NonNull<U, S> {
+            self.0.cast()
+        }
+    }
+
+    impl<T, S: AddressSpace> NonNullPointer for Owned<T, S> {
+        fn as_non_null(self) -> NonNull<T, S> {
+            self.0
+        }
+
+        unsafe fn from_non_null(ptr: NonNull<T, S>) -> Self {
+            Self(ptr)
+        }
+    }
+
+    impl<T, S: AddressSpace> OwningPointer for Owned<T, S> {}
+
+    #[test]
+    fn other_pointer_type() {
+        crate::host_space!(Heap: u16 = 256);
+        let b: Box<[u16; 2], _, Heap> = Box::new_in([1, 2], Heap::arena());
+        let (raw, alloc) = Box::into_raw_with_allocator(b);
+        let owned = Owned(NonNull::new(raw).unwrap());
+        // SAFE: the pointer was taken out of a box with the same allocator
+        let mut b: Box<_, _, Heap, _> = unsafe { Box::from_pointer_in(owned, alloc) };
+        b[1] += 1;
+        assert_eq!(*b, [1, 3]);
+        let (ptr, alloc) = Box::into_pointer_with_allocator(b);
+        assert_eq!(ptr.addr(), raw.addr());
+        assert_eq!(Heap::arena().live(), 1);
+        // SAFE: the pointer was taken out of a box with the same allocator
+        drop(unsafe { Box::from_pointer_in(ptr, alloc) });
+        assert_eq!(Heap::arena().live(), 0);
+    }
 }
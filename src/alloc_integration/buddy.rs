@@ -0,0 +1,461 @@
+//! A buddy allocator for power of two sized blocks
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{
+    clip,
+    lock::{Guard, Lock},
+    stats::{BlockState, Counters, HeapStats},
+};
+use crate::{
+    ptr::{MutPtr, NonNull},
+    space::{AddressSpace, NullEncoding},
+};
+
+type Link<S> = Option<NonNull<FreeBlock<S>, S>>;
+
+struct FreeBlock<S: AddressSpace> {
+    next: Link<S>,
+    prev: Link<S>,
+}
+
+/// Number of block sizes
+const ORDERS: usize = 16;
+
+struct Control<S: AddressSpace> {
+    heads: [Link<S>; ORDERS],
+    /// Address that block indices are relative to, aligned to the largest block size
+    origin: usize,
+    /// Start of the memory that is handed out, after the bitmaps
+    start: usize,
+    /// End of the memory that is handed out
+    end: usize,
+    /// The largest order that is used
+    top: usize,
+    /// Offset of the per-order bitmaps of free blocks from the start of the region
+    bitmap: usize,
+    bitmap_offsets: [usize; ORDERS],
+    counters: Counters,
+}
+
+/// A buddy allocator managing a `[u8; SIZE]` region
+///
+/// Allocations are rounded up to a power of two and aligned to their size. Blocks are split in
+/// half until they have the right size, and merged with their buddy when both are free. The free
+/// lists are linked through tiny pointers inside of the free blocks, and which blocks are free is
+/// tracked in bitmaps at the start of the region.
+///
+/// Like [`Arena`](crate::arena::Arena), the allocator is meant to be the static that the
+/// address space is declared on:
+///
+/// ```
+/// # use tinyptr::{boxed::Box, buddy::Buddy};
+/// static HEAP: Buddy<Heap, 0x8000> = Buddy::new();
+/// tinyptr::address_space!(pub Heap = static HEAP);
+///
+/// let b: Box<[u8; 512], _, Heap> = Box::new_in([0; 512], &HEAP);
+/// ```
+#[repr(C)]
+pub struct Buddy<S: AddressSpace, const SIZE: usize> {
+    region: UnsafeCell<[u8; SIZE]>,
+    control: UnsafeCell<Control<S>>,
+    initialized: AtomicBool,
+    lock: Lock,
+}
+
+// SAFE: the control structure and free blocks are only accessed while holding the lock
+unsafe impl<S: AddressSpace, const SIZE: usize> Sync for Buddy<S, SIZE> {}
+unsafe impl<S: AddressSpace, const SIZE: usize> Send for Buddy<S, SIZE> {}
+
+impl<S: AddressSpace> Control<S> {
+    /// The size of the smallest block
+    const MIN: usize = {
+        let size = core::mem::size_of::<FreeBlock<S>>();
+        let size = if size > S::GRANULE { size } else { S::GRANULE };
+        size.next_power_of_two()
+    };
+
+    const fn new() -> Self {
+        Self {
+            heads: [None; ORDERS],
+            origin: 0,
+            start: 0,
+            end: 0,
+            top: 0,
+            bitmap: 0,
+            bitmap_offsets: [0; ORDERS],
+            counters: Counters::new(),
+        }
+    }
+
+    fn bit(&self, region: *mut u8, addr: usize, order: usize) -> (*mut u8, u8) {
+        let index = (addr - self.origin) / (Self::MIN << order);
+        let byte = self.bitmap + self.bitmap_offsets[order] + index / 8;
+        (region.wrapping_add(byte), 1 << (index % 8))
+    }
+
+    /// # Safety
+    /// The block has to be inside of the managed memory of the region.
+    unsafe fn is_free(&self, region: *mut u8, addr: usize, order: usize) -> bool {
+        let (byte, mask) = self.bit(region, addr, order);
+        *byte & mask != 0
+    }
+
+    /// # Safety
+    /// The block has to be inside of the managed memory of the region and not be in use.
+    unsafe fn push(&mut self, region: *mut u8, block: *mut FreeBlock<S>, order: usize) {
+        let head = self.heads[order];
+        block.write(FreeBlock {
+            next: head,
+            prev: None,
+        });
+        if let Some(head) = head {
+            (*head.as_ptr().as_wide_ptr()).prev = link(block);
+        }
+        self.heads[order] = link(block);
+        let (byte, mask) = self.bit(region, block.addr(), order);
+        *byte |= mask;
+    }
+
+    /// # Safety
+    /// The block has to be in the free list of the order.
+    unsafe fn remove(&mut self, region: *mut u8, block: *mut FreeBlock<S>, order: usize) {
+        let FreeBlock { next, prev } = block.read();
+        if let Some(next) = next {
+            (*next.as_ptr().as_wide_ptr()).prev = prev;
+        }
+        match prev {
+            Some(prev) => (*prev.as_ptr().as_wide_ptr()).next = next,
+            None => self.heads[order] = next,
+        }
+        let (byte, mask) = self.bit(region, block.addr(), order);
+        *byte &= !mask;
+    }
+
+    /// Returns the order of the free block that starts at `addr`, if there is one
+    ///
+    /// # Safety
+    /// The address has to be inside of the managed memory of the region.
+    unsafe fn free_order(&self, region: *mut u8, addr: usize) -> Option<usize> {
+        (0..=self.top).rev().find(|&order| {
+            let size = Self::MIN << order;
+            addr.is_multiple_of(size)
+                && addr + size <= self.end
+                && self.is_free(region, addr, order)
+        })
+    }
+
+    /// Frees the largest aligned blocks that cover `start..end`
+    ///
+    /// # Safety
+    /// The memory has to be unused.
+    unsafe fn free_range(&mut self, region: *mut u8, mut start: usize, end: usize) {
+        while start < end {
+            let mut order = self.top;
+            while !start.is_multiple_of(Self::MIN << order) || start + (Self::MIN << order) > end {
+                order -= 1;
+            }
+            self.push(region, region.with_addr(start).cast(), order);
+            start += Self::MIN << order;
+        }
+    }
+}
+
+fn link<S: AddressSpace>(block: *mut FreeBlock<S>) -> Link<S> {
+    NonNull::new(MutPtr::new(block)?)
+}
+
+impl<S: AddressSpace, const SIZE: usize> Buddy<S, SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            region: UnsafeCell::new([0; SIZE]),
+            control: UnsafeCell::new(Control::new()),
+            initialized: AtomicBool::new(false),
+            lock: Lock::new(),
+        }
+    }
+
+    /// Places the bitmaps at the start of the region, and frees the rest of it
+    unsafe fn init(&self, control: &mut Control<S>) {
+        let min = Control::<S>::MIN;
+        let region = self.region.get().cast::<u8>();
+        let lo = region.addr().next_multiple_of(min);
+        let hi = (region.addr() + SIZE) & !(min - 1);
+        let Some((lo, hi)) = clip::encodable::<S>(lo, hi, min) else {
+            return;
+        };
+        control.top = ((hi - lo) / min).ilog2().min(ORDERS as u32 - 1) as usize;
+        control.origin = lo & !((min << control.top) - 1);
+        let mut bytes = 0;
+        for order in 0..=control.top {
+            control.bitmap_offsets[order] = bytes;
+            bytes += ((hi - control.origin) / (min << order)).div_ceil(8);
+        }
+        region.add(lo - region.addr()).write_bytes(0, bytes);
+        control.bitmap = lo - region.addr();
+        let start = (lo + bytes).next_multiple_of(min);
+        control.start = start;
+        control.end = hi;
+        // The block containing the null offset is never handed out
+        match S::Null::null()
+            .map(|null| S::decode(null) & !(min - 1))
+            .filter(|null| (start..hi).contains(null))
+        {
+            Some(null) => {
+                control.free_range(region, start, null);
+                control.free_range(region, null + min, hi);
+            }
+            None => control.free_range(region, start, hi),
+        }
+    }
+
+    /// Locks the allocator, initializing it on first use
+    fn lock(&self) -> Guard<'_> {
+        let guard = self.lock.lock();
+        // The flag is only accessed while the lock is held
+        if !self.initialized.load(Ordering::Relaxed) {
+            self.initialized.store(true, Ordering::Relaxed);
+            // SAFE: the lock is held, and the region is only used by the allocator
+            unsafe { self.init(&mut *self.control.get()) };
+        }
+        guard
+    }
+
+    /// Calls `f` with an iterator over all blocks, in address order
+    ///
+    /// The allocator is locked while `f` runs, so allocating from it in `f` deadlocks, or panics
+    /// with the `critical-section` feature.
+    pub fn blocks<R>(&self, f: impl FnOnce(Blocks<'_, S>) -> R) -> R {
+        let _guard = self.lock();
+        // SAFE: the lock is held until the iterator is gone
+        let control = unsafe { &*self.control.get() };
+        f(Blocks {
+            region: self.region.get().cast(),
+            pos: control.start,
+            null: S::Null::null().map(|null| S::decode(null) & !(Control::<S>::MIN - 1)),
+            control,
+        })
+    }
+
+    /// Returns a snapshot of the allocator statistics
+    pub fn stats(&self) -> HeapStats {
+        let _guard = self.lock();
+        // SAFE: the lock is held
+        let control = unsafe { &*self.control.get() };
+        let (mut free, mut largest_free) = (0, 0);
+        for (order, head) in control.heads.iter().enumerate() {
+            let size = Control::<S>::MIN << order;
+            let mut link = *head;
+            while let Some(block) = link {
+                free += size;
+                largest_free = size;
+                // SAFE: all blocks in the free lists are inside of the region
+                link = unsafe { (*block.as_ptr().as_wide_ptr()).next };
+            }
+        }
+        control.counters.snapshot(free, largest_free)
+    }
+
+    /// Returns the order of the blocks that an allocation with the layout is served from
+    fn order(layout: Layout) -> Option<usize> {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(Control::<S>::MIN)
+            .checked_next_power_of_two()?;
+        Some((size / Control::<S>::MIN).ilog2() as usize)
+    }
+}
+
+impl<S: AddressSpace, const SIZE: usize> Default for Buddy<S, SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<S: AddressSpace, const SIZE: usize> Allocator for &Buddy<S, SIZE> {
+    fn allocate(&self, layout: Layout) -> Result<core::ptr::NonNull<[u8]>, AllocError> {
+        let order = Buddy::<S, SIZE>::order(layout).ok_or(AllocError)?;
+        let _guard = self.lock();
+        // SAFE: the lock is held
+        let control = unsafe { &mut *self.control.get() };
+        if order > control.top {
+            return Err(AllocError);
+        }
+        let mut current = (order..=control.top)
+            .find(|&order| control.heads[order].is_some())
+            .ok_or(AllocError)?;
+        let region = self.region.get().cast::<u8>();
+        // SAFE: all blocks in the free lists are inside of the region
+        unsafe {
+            let block = control.heads[current]
+                .unwrap_unchecked()
+                .as_ptr()
+                .as_wide_ptr();
+            control.remove(region, block, current);
+            control.counters.allocate(Control::<S>::MIN << order);
+            while current > order {
+                current -= 1;
+                control.push(
+                    region,
+                    block.byte_add(Control::<S>::MIN << current),
+                    current,
+                );
+            }
+            let ptr = core::ptr::NonNull::new_unchecked(block.cast());
+            Ok(core::ptr::NonNull::slice_from_raw_parts(
+                ptr,
+                Control::<S>::MIN << order,
+            ))
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: core::ptr::NonNull<u8>, layout: Layout) {
+        let mut order = Buddy::<S, SIZE>::order(layout).unwrap_unchecked();
+        let _guard = self.lock();
+        let control = &mut *self.control.get();
+        let region = self.region.get().cast::<u8>();
+        control.counters.deallocate(Control::<S>::MIN << order);
+        let mut block = ptr.as_ptr().cast::<FreeBlock<S>>();
+        while order < control.top {
+            let size = Control::<S>::MIN << order;
+            let buddy = block.addr() ^ size;
+            if buddy + size > control.end || !control.is_free(region, buddy, order) {
+                break;
+            }
+            let buddy = block.with_addr(buddy);
+            control.remove(region, buddy, order);
+            block = block.with_addr(block.addr() & !size);
+            order += 1;
+        }
+        control.push(region, block, order);
+    }
+}
+
+/// An iterator over the blocks of a [`Buddy`] allocator, see [`Buddy::blocks`]
+///
+/// It yields the start, size and state of every block. The allocator doesn't record the size of
+/// allocations, so a used block is a run of allocated memory between two free blocks, which can
+/// hold several allocations. The block at the null offset is skipped.
+pub struct Blocks<'a, S: AddressSpace> {
+    region: *mut u8,
+    control: &'a Control<S>,
+    pos: usize,
+    null: Option<usize>,
+}
+
+impl<S: AddressSpace> Iterator for Blocks<'_, S> {
+    type Item = (NonNull<u8, S>, Layout, BlockState);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let min = Control::<S>::MIN;
+        if self.null == Some(self.pos) {
+            self.pos += min;
+        }
+        let (start, end) = (self.pos, self.control.end);
+        if start >= end {
+            return None;
+        }
+        // SAFE: the lock is held, and the positions are inside of the managed memory
+        let state = match unsafe { self.control.free_order(self.region, start) } {
+            Some(order) => {
+                self.pos += min << order;
+                BlockState::Free
+            }
+            None => {
+                self.pos += min;
+                while self.pos < end
+                    && self.null != Some(self.pos)
+                    && unsafe { self.control.free_order(self.region, self.pos) }.is_none()
+                {
+                    self.pos += min;
+                }
+                BlockState::Used
+            }
+        };
+        let ptr = NonNull::new(MutPtr::new(self.region.with_addr(start))?)?;
+        // SAFE: blocks are multiples of the smallest block size, which is a power of two
+        let layout = unsafe { Layout::from_size_align_unchecked(self.pos - start, min) };
+        Some((ptr, layout, state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::{Allocator, Layout};
+
+    use super::Buddy;
+    use crate::{
+        boxed::Box,
+        stats::{BlockState, HeapStats},
+    };
+
+    #[test]
+    fn split_and_merge() {
+        static HEAP: Buddy<Heap, 0x2000> = Buddy::new();
+        crate::address_space!(Heap: u16 = static HEAP);
+
+        let before = HEAP.stats();
+        let free = before.free;
+        let a: Box<[u8; 100], _, Heap> = Box::new_in([1; 100], &HEAP);
+        let b: Box<[u8; 100], _, Heap> = Box::new_in([2; 100], &HEAP);
+        assert_eq!(HEAP.stats().free, free - 256);
+        let used: Vec<_> = HEAP.blocks(|blocks| {
+            blocks
+                .filter(|&(_, _, state)| state == BlockState::Used)
+                .map(|(ptr, layout, _)| (ptr.as_ptr().as_wide_ptr(), layout.size()))
+                .collect()
+        });
+        // Depending on the alignment of the region, the blocks are adjacent and walked as one run
+        assert_eq!(used.iter().map(|&(_, size)| size).sum::<usize>(), 256);
+        for (ptr, size) in used {
+            assert_eq!((ptr.addr() % 128, size % 128), (0, 0));
+        }
+        assert_eq!((a[0], b[0]), (1, 2));
+        drop((a, b));
+        // The buddies are merged again
+        assert_eq!(
+            HEAP.stats(),
+            HeapStats {
+                peak: 256,
+                ..before
+            }
+        );
+        assert!(HEAP.blocks(|mut blocks| blocks.all(|(_, _, state)| state == BlockState::Free)));
+    }
+
+    #[test]
+    fn clips_to_the_address_space() {
+        static HEAP: Buddy<Heap, 1024> = Buddy::new();
+        crate::address_space!(Heap: u8 = static HEAP);
+
+        let stats = HEAP.stats();
+        assert!(stats.free > 0 && stats.free < 256);
+        HEAP.blocks(|mut blocks| {
+            assert!(blocks.all(|(ptr, layout, _)| ptr.addr() as usize + layout.size() <= 256));
+        });
+        let b: Box<[u8; 32], _, Heap> = Box::new_in([1; 32], &HEAP);
+        assert_eq!(b[31], 1);
+    }
+
+    #[test]
+    fn exhaustion() {
+        static HEAP: Buddy<Heap, 0x1000> = Buddy::new();
+        crate::address_space!(Heap: u16 = static HEAP);
+
+        let largest = HEAP.stats().largest_free;
+        let layout = Layout::from_size_align(largest, 1).unwrap();
+        let blocks: Vec<_> = core::iter::from_fn(|| (&HEAP).allocate(layout).ok()).collect();
+        assert!(!blocks.is_empty());
+        assert!(HEAP.stats().largest_free < largest);
+        for ptr in blocks {
+            assert_eq!(ptr.len(), largest);
+            // SAFE: the block was allocated with the same layout
+            unsafe { (&HEAP).deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(HEAP.stats().largest_free, largest);
+    }
+}
@@ -0,0 +1,46 @@
+//! Clipping of allocator regions to the part that can be encoded in an address space
+use crate::space::AddressSpace;
+
+/// Returns whether every granule of the unit at `addr` can be encoded in the address space
+fn unit_encodable<S: AddressSpace>(addr: usize, unit: usize) -> bool {
+    S::encode(addr + unit - S::GRANULE).is_some()
+}
+
+/// Returns the part of `lo..hi` that can be encoded in the address space
+///
+/// `lo` and `hi` have to be multiples of `unit`, which has to be a power of two that is at least
+/// the granule of the address space. The encodable addresses in the range are assumed to be
+/// contiguous.
+pub(crate) fn encodable<S: AddressSpace>(
+    lo: usize,
+    hi: usize,
+    unit: usize,
+) -> Option<(usize, usize)> {
+    let lo = if S::encode(lo).is_some() {
+        lo
+    } else {
+        // The region starts below the address space
+        let base = S::base().next_multiple_of(unit);
+        if base < lo || S::encode(base).is_none() {
+            return None;
+        }
+        base
+    };
+    if lo >= hi || !unit_encodable::<S>(lo, unit) {
+        return None;
+    }
+    if unit_encodable::<S>(hi - unit, unit) {
+        return Some((lo, hi));
+    }
+    // Binary search the first unit that can't be encoded
+    let (mut good, mut bad) = (lo / unit, (hi - unit) / unit);
+    while bad - good > 1 {
+        let mid = good + (bad - good) / 2;
+        if unit_encodable::<S>(mid * unit, unit) {
+            good = mid;
+        } else {
+            bad = mid;
+        }
+    }
+    Some((lo, bad * unit))
+}
@@ -0,0 +1,434 @@
+//! Detection of heap corruption, enabled by the `harden` feature
+//!
+//! [`Hardened`] wraps an allocator, surrounding every allocation with canaries and poisoning it
+//! when it is freed. Corruption that is found when an allocation is freed causes a panic, and
+//! [`Hardened::check_heap`] checks all live allocations at once.
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::UnsafeCell,
+    fmt,
+    ptr::NonNull,
+};
+
+use super::{arena::Arena, buddy::Buddy, lock::Lock, pool::Pool, tlsf::Tlsf};
+use crate::{ptr::MutPtr, space::AddressSpace};
+
+/// Written in front of every allocation
+const HEADER_CANARY: u32 = 0x7157_C0DE;
+/// Written after every allocation
+const FOOTER_CANARY: u32 = 0xCA11_AB1E;
+/// The value freed memory is filled with
+pub const POISON: u8 = 0xDD;
+
+const ALLOCATED: u8 = 0xA1;
+const FREED: u8 = 0xF7;
+
+type Link<S> = Option<crate::ptr::NonNull<Header<S>, S>>;
+
+#[repr(C)]
+struct Header<S: AddressSpace> {
+    /// The neighbours in the list of live allocations
+    prev: Link<S>,
+    next: Link<S>,
+    /// Offset of the footer from the header
+    end: usize,
+    canary: u32,
+    state: u8,
+}
+
+/// The kind of corruption that was found in an allocation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Fault {
+    /// The canary in front of the allocation was overwritten
+    HeaderCanary,
+    /// The canary after the allocation was overwritten
+    FooterCanary,
+    /// The allocation has already been freed
+    DoubleFree,
+    /// The state byte of the allocation is invalid
+    State(u8),
+    /// The size of the allocation doesn't match its layout or block
+    Size,
+    /// The links between the allocations were overwritten
+    Link,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::HeaderCanary => write!(f, "header canary overwritten"),
+            Self::FooterCanary => write!(f, "footer canary overwritten"),
+            Self::DoubleFree => write!(f, "double free"),
+            Self::State(state) => write!(f, "invalid block state {state:#x}"),
+            Self::Size => write!(f, "allocation size mismatch"),
+            Self::Link => write!(f, "allocation list corrupted"),
+        }
+    }
+}
+
+/// A corrupted allocation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Corruption<O> {
+    /// The offset of the header of the allocation
+    pub offset: O,
+    pub fault: Fault,
+}
+
+/// The result of [`Hardened::check_heap`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HeapReport<O> {
+    /// Number of allocations that were checked
+    pub allocations: usize,
+    /// The first corrupted allocation, checking stops there
+    pub corruption: Option<Corruption<O>>,
+}
+
+impl<O> HeapReport<O> {
+    pub fn is_ok(&self) -> bool {
+        self.corruption.is_none()
+    }
+}
+
+/// An allocator whose memory is inside of an address space
+///
+/// This lets [`Hardened`] derive its address space from the allocator it wraps.
+pub trait SpaceAllocator: Allocator {
+    type Space: AddressSpace;
+}
+
+impl<S: AddressSpace, const SIZE: usize> SpaceAllocator for &Arena<S, SIZE> {
+    type Space = S;
+}
+
+impl<S: AddressSpace, const SIZE: usize> SpaceAllocator for &Pool<S, SIZE> {
+    type Space = S;
+}
+
+impl<S: AddressSpace, const SIZE: usize> SpaceAllocator for &Tlsf<S, SIZE> {
+    type Space = S;
+}
+
+impl<S: AddressSpace, const SIZE: usize> SpaceAllocator for &Buddy<S, SIZE> {
+    type Space = S;
+}
+
+struct List<S: AddressSpace> {
+    head: Link<S>,
+    len: usize,
+}
+
+/// An allocator wrapper that detects heap corruption
+///
+/// Every allocation is preceded by a header with a canary and a state byte, and followed by a
+/// footer canary. They are checked when the allocation is freed, which panics if they were
+/// overwritten or if the allocation has already been freed. Freed memory is filled with
+/// [`POISON`].
+///
+/// The headers link all live allocations into a list, so that [`check_heap`](Hardened::check_heap)
+/// only checks allocations made through the wrapper, whatever allocator it wraps. Like the
+/// allocators, it is shared by reference:
+///
+/// ```
+/// # use tinyptr::{boxed::Box, harden::Hardened, tlsf::Tlsf};
+/// # static HEAP: Tlsf<Heap, 0x8000> = Tlsf::new();
+/// # tinyptr::address_space!(Heap = static HEAP);
+/// static HARDENED: Hardened<&Tlsf<Heap, 0x8000>> = Hardened::new(&HEAP);
+///
+/// let b: Box<u32, _, Heap> = Box::new_in(42, &HARDENED);
+/// assert!(HARDENED.check_heap().is_ok());
+/// ```
+///
+/// Double frees are only detected as long as the memory hasn't been reused.
+pub struct Hardened<A, S: AddressSpace = <A as SpaceAllocator>::Space> {
+    inner: A,
+    list: UnsafeCell<List<S>>,
+    lock: Lock,
+}
+
+// SAFE: the list is only accessed while holding the lock
+unsafe impl<A: Sync, S: AddressSpace> Sync for Hardened<A, S> {}
+unsafe impl<A: Send, S: AddressSpace> Send for Hardened<A, S> {}
+
+impl<A: SpaceAllocator> Hardened<A, A::Space> {
+    pub const fn new(inner: A) -> Self {
+        Self::with_space(inner)
+    }
+}
+
+impl<A, S: AddressSpace> Hardened<A, S> {
+    /// Wraps an allocator whose memory is inside of the address space `S`
+    ///
+    /// This is for allocators that don't implement [`SpaceAllocator`].
+    pub const fn with_space(inner: A) -> Self {
+        Self {
+            inner,
+            list: UnsafeCell::new(List { head: None, len: 0 }),
+            lock: Lock::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// Checks the canaries and state of every live allocation
+    pub fn check_heap(&self) -> HeapReport<S::Offset> {
+        let mut report = HeapReport {
+            allocations: 0,
+            corruption: None,
+        };
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held
+        let list = unsafe { &*self.list.get() };
+        let (mut link, mut prev) = (list.head, None);
+        while let Some(header) = link {
+            let base = header.as_ptr().as_wide_ptr();
+            let fault = if report.allocations == list.len {
+                // There are more allocations in the list than were made, so it has a cycle
+                Err(Fault::Link)
+            } else {
+                // SAFE: the header is in the address space, and the previous header linking to it
+                // was intact
+                unsafe {
+                    check::<S>(base.cast(), usize::MAX).and_then(|()| {
+                        if (*base).prev == prev {
+                            Ok(())
+                        } else {
+                            Err(Fault::Link)
+                        }
+                    })
+                }
+            };
+            if let Err(fault) = fault {
+                report.corruption = Some(Corruption {
+                    offset: header.addr(),
+                    fault,
+                });
+                return report;
+            }
+            report.allocations += 1;
+            prev = link;
+            // SAFE: the header is valid
+            link = unsafe { (*base).next };
+        }
+        report
+    }
+
+    /// Adds an allocation to the list
+    ///
+    /// # Safety
+    /// The header has to be initialized, except for its links.
+    unsafe fn link(&self, header: *mut Header<S>) -> Result<(), AllocError> {
+        let link = crate::ptr::NonNull::new(MutPtr::new(header).ok_or(AllocError)?);
+        let _guard = self.lock.lock();
+        let list = &mut *self.list.get();
+        (*header).prev = None;
+        (*header).next = list.head;
+        if let Some(head) = list.head {
+            (*head.as_ptr().as_wide_ptr()).prev = link;
+        }
+        list.head = link;
+        list.len += 1;
+        Ok(())
+    }
+
+    /// Removes an allocation from the list
+    ///
+    /// # Safety
+    /// The header has to have been checked.
+    unsafe fn unlink(&self, header: *mut Header<S>) -> Result<(), Fault> {
+        let _guard = self.lock.lock();
+        let list = &mut *self.list.get();
+        let link = crate::ptr::NonNull::new(MutPtr::new(header).ok_or(Fault::Link)?);
+        let (prev, next) = ((*header).prev, (*header).next);
+        let prev_next = match prev {
+            Some(prev) => &raw mut (*prev.as_ptr().as_wide_ptr()).next,
+            None => &raw mut list.head,
+        };
+        if *prev_next != link
+            || next.is_some_and(|next| (*next.as_ptr().as_wide_ptr()).prev != link)
+        {
+            return Err(Fault::Link);
+        }
+        *prev_next = next;
+        if let Some(next) = next {
+            (*next.as_ptr().as_wide_ptr()).prev = prev;
+        }
+        list.len -= 1;
+        Ok(())
+    }
+}
+
+/// Returns the size of the header of an allocation with the alignment
+fn header_size<S: AddressSpace>(align: usize) -> usize {
+    core::mem::size_of::<Header<S>>()
+        .next_multiple_of(align.max(core::mem::align_of::<Header<S>>()))
+}
+
+/// Returns the layout of the allocation, including the canaries
+fn outer_layout<S: AddressSpace>(layout: Layout) -> Result<Layout, AllocError> {
+    let size = header_size::<S>(layout.align())
+        .checked_add(layout.size())
+        .and_then(|size| size.checked_add(core::mem::size_of::<u32>()))
+        .ok_or(AllocError)?;
+    let align = layout.align().max(core::mem::align_of::<Header<S>>());
+    Layout::from_size_align(size, align).map_err(|_| AllocError)
+}
+
+/// Checks the canaries and state of an allocation whose header is at `base`
+///
+/// The footer has to be inside of the address space, and at most `limit` bytes after `base`.
+///
+/// # Safety
+/// `base` has to point to a header.
+unsafe fn check<S: AddressSpace>(base: *mut u8, limit: usize) -> Result<(), Fault> {
+    // The fields are read one at a time, since the start of a freed header may have been
+    // overwritten by the free list of the inner allocator. `end` is only read once the header is
+    // known to belong to a live allocation.
+    let header = base.cast::<Header<S>>();
+    if (&raw const (*header).canary).read() != HEADER_CANARY {
+        return Err(Fault::HeaderCanary);
+    }
+    match (&raw const (*header).state).read() {
+        ALLOCATED => {}
+        FREED => return Err(Fault::DoubleFree),
+        state => return Err(Fault::State(state)),
+    }
+    let end = (&raw const (*header).end).read();
+    let footer = base.addr().checked_add(end).ok_or(Fault::Size)?;
+    if end > limit - core::mem::size_of::<u32>() || S::encode(footer & !(S::GRANULE - 1)).is_none()
+    {
+        return Err(Fault::Size);
+    }
+    if base.add(end).cast::<u32>().read_unaligned() != FOOTER_CANARY {
+        return Err(Fault::FooterCanary);
+    }
+    Ok(())
+}
+
+unsafe impl<A: Allocator, S: AddressSpace> Allocator for &Hardened<A, S> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let outer = outer_layout::<S>(layout)?;
+        let base = self.inner.allocate(outer)?.cast::<u8>();
+        let header = header_size::<S>(layout.align());
+        // SAFE: the memory is large enough for the header, allocation and footer
+        unsafe {
+            let end = header + layout.size();
+            base.cast::<Header<S>>().write(Header {
+                prev: None,
+                next: None,
+                end,
+                canary: HEADER_CANARY,
+                state: ALLOCATED,
+            });
+            base.add(end).cast::<u32>().write_unaligned(FOOTER_CANARY);
+            if let Err(err) = self.link(base.cast().as_ptr()) {
+                self.inner.deallocate(base, outer);
+                return Err(err);
+            }
+            Ok(NonNull::slice_from_raw_parts(
+                base.add(header),
+                layout.size(),
+            ))
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let header = header_size::<S>(layout.align());
+        let base = ptr.sub(header);
+        let outer = outer_layout::<S>(layout).unwrap_unchecked();
+        let fault = match check::<S>(base.as_ptr(), outer.size()) {
+            Ok(()) if (*base.cast::<Header<S>>().as_ptr()).end != header + layout.size() => {
+                Err(Fault::Size)
+            }
+            Ok(()) => self.unlink(base.cast().as_ptr()),
+            result => result,
+        };
+        if let Err(fault) = fault {
+            let offset = crate::ptr_to_offset::<S>(base.as_ptr().cast());
+            panic!("heap corruption at offset {offset:?}: {fault}");
+        }
+        (*base.cast::<Header<S>>().as_ptr()).state = FREED;
+        ptr.write_bytes(POISON, layout.size() + core::mem::size_of::<u32>());
+        self.inner.deallocate(base, outer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::{Allocator, Layout};
+
+    use super::{Fault, Hardened, POISON};
+    use crate::{arena::Arena, boxed::Box, buddy::Buddy, pool::Pool, tlsf::Tlsf};
+
+    /// Allocates from the wrapper and checks that the heap stays intact
+    fn exercise<A: Allocator, S: crate::space::AddressSpace>(hardened: &Hardened<A, S>) {
+        let layout = Layout::new::<[u8; 24]>();
+        let a = hardened.allocate(layout).unwrap();
+        let b = hardened.allocate(layout).unwrap();
+        assert_eq!(hardened.check_heap().allocations, 2);
+        // SAFE: the blocks were allocated with the same layout
+        unsafe {
+            a.cast::<u8>().write_bytes(1, 24);
+            hardened.deallocate(a.cast(), layout);
+            assert_eq!(a.cast::<u8>().read(), POISON);
+            hardened.deallocate(b.cast(), layout);
+        }
+        let report = hardened.check_heap();
+        assert!(report.is_ok());
+        assert_eq!(report.allocations, 0);
+    }
+
+    #[test]
+    fn every_allocator() {
+        static ARENA: Arena<A, 1024> = Arena::new();
+        static POOL: Pool<P, 1024> = Pool::new();
+        static TLSF: Tlsf<T, 1024> = Tlsf::new();
+        static BUDDY: Buddy<B, 1024> = Buddy::new();
+        crate::address_space!(A: u16 = static ARENA);
+        crate::address_space!(P: u16 = static POOL);
+        crate::address_space!(T: u16 = static TLSF);
+        crate::address_space!(B: u16 = static BUDDY);
+        crate::host_space!(Host: u16 = 1024);
+
+        exercise(&Hardened::new(&ARENA));
+        exercise(&Hardened::new(&POOL));
+        exercise(&Hardened::new(&TLSF));
+        exercise(&Hardened::new(&BUDDY));
+        exercise(&Hardened::<_, Host>::with_space(Host::arena()));
+    }
+
+    #[test]
+    fn overflow() {
+        static HEAP: Tlsf<Heap, 1024> = Tlsf::new();
+        crate::address_space!(Heap: u16 = static HEAP);
+        static HARDENED: Hardened<&Tlsf<Heap, 1024>> = Hardened::new(&HEAP);
+
+        let b: Box<u32, _, Heap> = Box::new_in(1, &HARDENED);
+        let layout = Layout::new::<[u8; 8]>();
+        let ptr = (&HARDENED).allocate(layout).unwrap().cast::<u8>();
+        assert!(HARDENED.check_heap().is_ok());
+        // SAFE: the footer is part of the block that the inner allocator handed out
+        unsafe { ptr.add(8).write(0) };
+        let report = HARDENED.check_heap();
+        assert_eq!(report.corruption.unwrap().fault, Fault::FooterCanary);
+        assert_eq!(*b, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn double_free() {
+        static HEAP: Arena<Heap, 1024> = Arena::new();
+        crate::address_space!(Heap: u16 = static HEAP);
+        static HARDENED: Hardened<&Arena<Heap, 1024>> = Hardened::new(&HEAP);
+
+        let layout = Layout::new::<u64>();
+        let ptr = (&HARDENED).allocate(layout).unwrap().cast();
+        // SAFE: the second free is caught before the inner allocator sees it
+        unsafe {
+            (&HARDENED).deallocate(ptr, layout);
+            (&HARDENED).deallocate(ptr, layout);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! The lock for allocators that are shared between threads
+//!
+//! With the `critical-section` feature, the allocators are locked by entering a critical section
+//! through the [`critical-section`](https://docs.rs/critical-section) crate. On single core
+//! targets this masks interrupts, so an allocator can also be used from interrupt handlers.
+//! Critical sections are reentrant, so the lock also keeps a flag and panics if it is taken
+//! again while it is held, for example from inside of a heap walk.
+//!
+//! Otherwise a spin lock is used, which needs compare-and-swap. Interrupt handlers must not
+//! allocate, since they would spin forever if they interrupt code that holds the lock.
+//!
+//! Guards are only held as scoped locals, so locks are released in the reverse order that they
+//! were taken in.
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(all(not(feature = "critical-section"), not(target_has_atomic = "8")))]
+compile_error!(
+    "the allocators need the `critical-section` feature on targets without compare-and-swap"
+);
+
+#[cfg(not(feature = "critical-section"))]
+pub(crate) struct Lock(AtomicBool);
+
+#[cfg(not(feature = "critical-section"))]
+pub(crate) struct Guard<'a>(&'a AtomicBool);
+
+#[cfg(not(feature = "critical-section"))]
+impl Lock {
+    pub(crate) const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    pub(crate) fn lock(&self) -> Guard<'_> {
+        while self
+            .0
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        Guard(&self.0)
+    }
+}
+
+#[cfg(not(feature = "critical-section"))]
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// The flag is only accessed inside of the critical section, atomics are used because they are
+/// `Sync` and loads and stores are available on every target
+#[cfg(feature = "critical-section")]
+pub(crate) struct Lock(AtomicBool);
+
+#[cfg(feature = "critical-section")]
+pub(crate) struct Guard<'a>(&'a AtomicBool, critical_section::RestoreState);
+
+#[cfg(feature = "critical-section")]
+impl Lock {
+    pub(crate) const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    pub(crate) fn lock(&self) -> Guard<'_> {
+        // SAFE: guards are only held as scoped locals, so critical sections are released in the
+        // reverse order that they were acquired in
+        let state = unsafe { critical_section::acquire() };
+        if self.0.load(Ordering::Relaxed) {
+            // SAFE: the state was returned by the matching acquire
+            unsafe { critical_section::release(state) };
+            panic!("allocator locked while it is already locked");
+        }
+        self.0.store(true, Ordering::Relaxed);
+        Guard(&self.0, state)
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Relaxed);
+        // SAFE: the state was returned by the matching acquire
+        unsafe { critical_section::release(self.1) };
+    }
+}
@@ -1,5 +1,10 @@
-
-#[doc(inline)]
-pub use alloc::alloc;
-
+pub mod arena;
 pub mod boxed;
+pub mod buddy;
+mod clip;
+#[cfg(feature = "harden")]
+pub mod harden;
+pub(crate) mod lock;
+pub mod pool;
+pub mod stats;
+pub mod tlsf;
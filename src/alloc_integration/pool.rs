@@ -0,0 +1,323 @@
+//! A pool allocator with fixed size classes
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::UnsafeCell,
+};
+
+use super::{
+    lock::Lock,
+    stats::{BlockState, Counters, HeapStats},
+};
+use crate::{
+    ptr::{MutPtr, NonNull},
+    space::AddressSpace,
+};
+
+/// The block sizes of the size classes of a [`Pool`]
+pub const SIZE_CLASSES: [usize; 5] = [8, 16, 32, 64, 128];
+
+const CLASSES: usize = SIZE_CLASSES.len();
+
+struct FreeBlock<S: AddressSpace> {
+    next: Option<NonNull<FreeBlock<S>, S>>,
+}
+
+struct State<S: AddressSpace> {
+    heads: [Option<NonNull<FreeBlock<S>, S>>; CLASSES],
+    /// Number of bytes at the start of the region that have been split into blocks
+    carved: usize,
+    in_use: [usize; CLASSES],
+    free: [usize; CLASSES],
+    counters: Counters,
+}
+
+/// Occupancy of one size class of a [`Pool`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    /// The block size of the class
+    pub size: usize,
+    /// Number of allocated blocks
+    pub in_use: usize,
+    /// Number of blocks on the free list
+    pub free: usize,
+}
+
+/// A pool allocator managing a `[u8; SIZE]` region
+///
+/// Allocations are rounded up to one of the [`SIZE_CLASSES`], and blocks are aligned to their
+/// size. Blocks are split off the region when a class runs out, and freed blocks are kept on a
+/// per-class free list that is linked through tiny pointers inside of the blocks. Blocks are
+/// never returned to the region, so every class keeps its peak number of blocks.
+///
+/// Like [`Arena`](crate::arena::Arena), the pool is meant to be the static that the address
+/// space is declared on:
+///
+/// ```
+/// # use tinyptr::{boxed::Box, pool::Pool};
+/// static POOL: Pool<Heap, 4096> = Pool::new();
+/// tinyptr::address_space!(pub Heap = static POOL);
+///
+/// let b: Box<u32, _, Heap> = Box::new_in(42, &POOL);
+/// ```
+#[repr(C)]
+pub struct Pool<S: AddressSpace, const SIZE: usize> {
+    region: UnsafeCell<[u8; SIZE]>,
+    state: UnsafeCell<State<S>>,
+    lock: Lock,
+}
+
+// SAFE: the state is only accessed while holding the lock
+unsafe impl<S: AddressSpace, const SIZE: usize> Sync for Pool<S, SIZE> {}
+unsafe impl<S: AddressSpace, const SIZE: usize> Send for Pool<S, SIZE> {}
+
+impl<S: AddressSpace, const SIZE: usize> Pool<S, SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            region: UnsafeCell::new([0; SIZE]),
+            state: UnsafeCell::new(State {
+                heads: [None; CLASSES],
+                carved: 0,
+                in_use: [0; CLASSES],
+                free: [0; CLASSES],
+                counters: Counters::new(),
+            }),
+            lock: Lock::new(),
+        }
+    }
+
+    /// Returns the size class that an allocation with the layout is served from
+    fn class_of(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        SIZE_CLASSES.iter().position(|&class| class >= size)
+    }
+
+    /// Returns the occupancy of every size class
+    pub fn class_stats(&self) -> [ClassStats; CLASSES] {
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held
+        let state = unsafe { &*self.state.get() };
+        core::array::from_fn(|class| ClassStats {
+            size: SIZE_CLASSES[class],
+            in_use: state.in_use[class],
+            free: state.free[class],
+        })
+    }
+
+    /// Calls `f` with an iterator over the free memory of the pool
+    ///
+    /// The pool doesn't record where its used blocks are, so unlike the other allocators it only
+    /// yields the free blocks of every size class, ordered by class, and then the part of the
+    /// region that hasn't been split into blocks yet. The number of used blocks is reported by
+    /// [`class_stats`](Pool::class_stats).
+    ///
+    /// The allocator is locked while `f` runs, so allocating from it in `f` deadlocks, or panics
+    /// with the `critical-section` feature.
+    pub fn blocks<R>(&self, f: impl FnOnce(Blocks<'_, S>) -> R) -> R {
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held until the iterator is gone
+        let state = unsafe { &*self.state.get() };
+        f(Blocks {
+            region: self.region.get().cast(),
+            size: SIZE,
+            tail: true,
+            class: 0,
+            next: state.heads[0],
+            state,
+        })
+    }
+
+    /// Returns a snapshot of the allocator statistics
+    ///
+    /// Free memory includes the blocks on the free lists and the part of the region that hasn't
+    /// been split into blocks yet.
+    pub fn stats(&self) -> HeapStats {
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held
+        let state = unsafe { &*self.state.get() };
+        let uncarved = SIZE - state.carved;
+        let free = (0..CLASSES)
+            .map(|class| state.free[class] * SIZE_CLASSES[class])
+            .sum::<usize>();
+        let largest_free = (0..CLASSES)
+            .filter(|&class| state.free[class] > 0)
+            .map(|class| SIZE_CLASSES[class])
+            .fold(uncarved, usize::max);
+        state.counters.snapshot(free + uncarved, largest_free)
+    }
+
+    /// Splits a new block of the class off the region
+    fn carve(&self, state: &mut State<S>, class: usize) -> Option<*mut u8> {
+        let size = SIZE_CLASSES[class];
+        let align = size.max(S::GRANULE);
+        let region = self.region.get().cast::<u8>();
+        let end = region.addr() + SIZE;
+        let mut start = (region.addr() + state.carved).next_multiple_of(align);
+        loop {
+            if start + size > end {
+                return None;
+            }
+            let ptr = region.with_addr(start);
+            // Skips addresses that can't be encoded, like the null offset
+            if MutPtr::<u8, S>::new(ptr).is_some() {
+                state.carved = start + size - region.addr();
+                return Some(ptr);
+            }
+            start += align;
+        }
+    }
+}
+
+impl<S: AddressSpace, const SIZE: usize> Default for Pool<S, SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<S: AddressSpace, const SIZE: usize> Allocator for &Pool<S, SIZE> {
+    fn allocate(&self, layout: Layout) -> Result<core::ptr::NonNull<[u8]>, AllocError> {
+        let class = Pool::<S, SIZE>::class_of(layout).ok_or(AllocError)?;
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held
+        let state = unsafe { &mut *self.state.get() };
+        let ptr = match state.heads[class] {
+            Some(link) => {
+                let block = link.as_ptr().as_wide_ptr();
+                // SAFE: blocks on the free list are not allocated
+                state.heads[class] = unsafe { (*block).next };
+                state.free[class] -= 1;
+                block.cast()
+            }
+            None => self.carve(state, class).ok_or(AllocError)?,
+        };
+        state.in_use[class] += 1;
+        state.counters.allocate(SIZE_CLASSES[class]);
+        // SAFE: the pointer is derived from the region
+        let ptr = unsafe { core::ptr::NonNull::new_unchecked(ptr) };
+        Ok(core::ptr::NonNull::slice_from_raw_parts(
+            ptr,
+            SIZE_CLASSES[class],
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: core::ptr::NonNull<u8>, layout: Layout) {
+        let class = Pool::<S, SIZE>::class_of(layout).unwrap_unchecked();
+        let _guard = self.lock.lock();
+        let state = &mut *self.state.get();
+        let block = ptr.as_ptr().cast::<FreeBlock<S>>();
+        block.write(FreeBlock {
+            next: state.heads[class],
+        });
+        state.heads[class] = NonNull::new(MutPtr::new_unchecked(block));
+        state.in_use[class] -= 1;
+        state.counters.deallocate(SIZE_CLASSES[class]);
+        state.free[class] += 1;
+    }
+}
+
+/// An iterator over the free memory of a [`Pool`], see [`Pool::blocks`]
+pub struct Blocks<'a, S: AddressSpace> {
+    region: *mut u8,
+    size: usize,
+    /// Whether the uncarved rest of the region still has to be yielded
+    tail: bool,
+    class: usize,
+    next: Option<NonNull<FreeBlock<S>, S>>,
+    state: &'a State<S>,
+}
+
+impl<S: AddressSpace> Iterator for Blocks<'_, S> {
+    type Item = (NonNull<u8, S>, Layout, BlockState);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.class < CLASSES {
+            if let Some(link) = self.next {
+                // SAFE: the lock is held, and blocks on the free list are not allocated
+                self.next = unsafe { (*link.as_ptr().as_wide_ptr()).next };
+                let size = SIZE_CLASSES[self.class];
+                // SAFE: the size classes are powers of two
+                let layout = unsafe { Layout::from_size_align_unchecked(size, size) };
+                return Some((link.cast(), layout, BlockState::Free));
+            }
+            self.class += 1;
+            self.next = self.state.heads.get(self.class).copied().flatten();
+        }
+        let len = self.size - self.state.carved;
+        if !core::mem::take(&mut self.tail) || len == 0 {
+            return None;
+        }
+        let ptr = NonNull::new(MutPtr::new(self.region.wrapping_add(self.state.carved))?)?;
+        let layout = Layout::from_size_align(len, 1).ok()?;
+        Some((ptr, layout, BlockState::Free))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::{Allocator, Layout};
+
+    use super::{Pool, SIZE_CLASSES};
+    use crate::{boxed::Box, stats::BlockState};
+
+    #[test]
+    fn size_classes() {
+        static POOL: Pool<Heap, 1024> = Pool::new();
+        crate::address_space!(Heap: u16 = static POOL);
+
+        let a: Box<u8, _, Heap> = Box::new_in(1, &POOL);
+        let b: Box<[u8; 20], _, Heap> = Box::new_in([2; 20], &POOL);
+        let c: Box<[u8; 20], _, Heap> = Box::new_in([3; 20], &POOL);
+        let stats = POOL.class_stats();
+        assert_eq!((stats[0].in_use, stats[2].in_use), (1, 2));
+        assert_eq!(stats.map(|class| class.size), SIZE_CLASSES);
+        drop(b);
+        assert_eq!(
+            (POOL.class_stats()[2].in_use, POOL.class_stats()[2].free),
+            (1, 1)
+        );
+        let free: Vec<_> =
+            POOL.blocks(|blocks| blocks.map(|(_, layout, _)| layout.size()).collect());
+        assert_eq!(free[0], 32);
+        assert!(POOL.blocks(|mut blocks| blocks.all(|(_, _, state)| state == BlockState::Free)));
+        // The freed block is reused
+        let d: Box<[u8; 17], _, Heap> = Box::new_in([4; 17], &POOL);
+        assert_eq!(POOL.class_stats()[2].free, 0);
+        assert_eq!((*a, c[0], d[0]), (1, 3, 4));
+        assert_eq!(POOL.stats().in_use, 72);
+    }
+
+    #[test]
+    fn deallocate_with_returned_size() {
+        static POOL: Pool<Heap, 256> = Pool::new();
+        crate::address_space!(Heap: u16 = static POOL);
+
+        let block = (&POOL).allocate(Layout::new::<[u8; 5]>()).unwrap();
+        assert_eq!(block.len(), 8);
+        let layout = Layout::from_size_align(block.len(), 1).unwrap();
+        // SAFE: the layout fits the returned block
+        unsafe { (&POOL).deallocate(block.cast(), layout) };
+        let stats = POOL.stats();
+        assert_eq!((stats.in_use, stats.allocations), (0, [0; 16]));
+    }
+
+    #[test]
+    fn alignment() {
+        static POOL: Pool<Heap, 1024> = Pool::new();
+        crate::address_space!(Heap: u16 = static POOL);
+
+        let layout = Layout::from_size_align(8, 64).unwrap();
+        let ptr = (&POOL).allocate(layout).unwrap();
+        assert_eq!(ptr.cast::<u8>().as_ptr().addr() % 64, 0);
+        assert!((&POOL).allocate(Layout::new::<[u8; 129]>()).is_err());
+    }
+
+    #[test]
+    fn exhaustion() {
+        static POOL: Pool<Heap, 256> = Pool::new();
+        crate::address_space!(Heap: u16 = static POOL);
+
+        let layout = Layout::new::<[u8; 128]>();
+        let blocks: Vec<_> = core::iter::from_fn(|| (&POOL).allocate(layout).ok()).collect();
+        assert!(!blocks.is_empty() && blocks.len() <= 2);
+        assert!(POOL.stats().largest_free < 128);
+    }
+}
@@ -0,0 +1,93 @@
+//! Statistics about the allocators of this crate
+
+/// A snapshot of the state of an allocator
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Number of bytes in allocated blocks
+    ///
+    /// Allocations are counted with the size of the block that serves them, which includes the
+    /// rounding of the allocator but not its headers.
+    pub in_use: usize,
+    /// The largest value `in_use` has had
+    pub peak: usize,
+    /// Number of bytes in free blocks
+    pub free: usize,
+    /// Size of the largest free block
+    pub largest_free: usize,
+    /// Number of live allocations by the size class of their block
+    ///
+    /// Class `i` counts blocks of `2^i` to `2^(i + 1) - 1` bytes, the first class includes empty
+    /// blocks and the last one all larger blocks.
+    pub allocations: [usize; HeapStats::CLASSES],
+}
+
+impl HeapStats {
+    /// Number of size classes in [`allocations`](HeapStats::allocations)
+    pub const CLASSES: usize = 16;
+
+    /// Returns the size class of an allocation
+    pub fn class_of(size: usize) -> usize {
+        (size.max(1).ilog2() as usize).min(Self::CLASSES - 1)
+    }
+
+    /// Returns the fraction of free memory that is not part of the largest free block, in
+    /// thousandths
+    ///
+    /// This is 0 if all free memory is in one block, and approaches 1000 as the free memory is
+    /// split into more and more small blocks. It is computed with integers, so that targets
+    /// without a floating point unit don't need soft-float routines.
+    pub fn fragmentation(&self) -> u32 {
+        if self.free == 0 {
+            return 0;
+        }
+        ((self.free - self.largest_free) as u64 * 1000 / self.free as u64) as u32
+    }
+}
+
+/// Whether a block of a heap walk is allocated
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BlockState {
+    Used,
+    Free,
+}
+
+/// The counters that an allocator keeps for its [`HeapStats`]
+///
+/// They are updated with the size of the block that serves an allocation, since the layout that
+/// an allocation is freed with can be larger than the one it was requested with.
+pub(crate) struct Counters {
+    in_use: usize,
+    peak: usize,
+    allocations: [usize; HeapStats::CLASSES],
+}
+
+impl Counters {
+    pub(crate) const fn new() -> Self {
+        Self {
+            in_use: 0,
+            peak: 0,
+            allocations: [0; HeapStats::CLASSES],
+        }
+    }
+
+    pub(crate) fn allocate(&mut self, size: usize) {
+        self.in_use += size;
+        self.peak = self.peak.max(self.in_use);
+        self.allocations[HeapStats::class_of(size)] += 1;
+    }
+
+    pub(crate) fn deallocate(&mut self, size: usize) {
+        self.in_use -= size;
+        self.allocations[HeapStats::class_of(size)] -= 1;
+    }
+
+    pub(crate) fn snapshot(&self, free: usize, largest_free: usize) -> HeapStats {
+        HeapStats {
+            in_use: self.in_use,
+            peak: self.peak,
+            free,
+            largest_free,
+            allocations: self.allocations,
+        }
+    }
+}
@@ -0,0 +1,595 @@
+//! A Two-Level Segregated Fit allocator with constant time operations
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::UnsafeCell,
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{
+    clip,
+    lock::{Guard, Lock},
+    stats::{BlockState, Counters, HeapStats},
+};
+use crate::{
+    ptr::{MutPtr, NonNull},
+    space::{AddressSpace, NullEncoding},
+};
+
+type Link<S> = Option<NonNull<Block<S>, S>>;
+
+#[repr(C)]
+struct Block<S: AddressSpace> {
+    /// The block that is physically before this one, `None` for the first block
+    prev_phys: Link<S>,
+    /// Size of the payload, with [`FREE`] set if the block is free
+    size: usize,
+    // The free list links are only valid in free blocks, they overlap the payload
+    next_free: Link<S>,
+    prev_free: Link<S>,
+}
+
+const FREE: usize = 1;
+
+/// Number of second level lists per first level list, as a power of two
+const SL_LOG: u32 = 4;
+const SL: usize = 1 << SL_LOG;
+/// Number of first level lists
+const FL: usize = 16;
+
+struct Control<S: AddressSpace> {
+    fl_bitmap: u32,
+    sl_bitmap: [u32; FL],
+    heads: [[Link<S>; SL]; FL],
+    /// Offset of the first block from the start of the region, `None` if there is none
+    ///
+    /// This is relative, so that the block pointers are derived from the allocator's region.
+    first: Option<usize>,
+    counters: Counters,
+}
+
+/// A Two-Level Segregated Fit allocator managing a `[u8; SIZE]` region
+///
+/// Free blocks are kept in segregated lists that are found through two levels of bitmaps, so
+/// allocating and deallocating take constant time. Block headers link to their physical
+/// neighbours and free list neighbours through tiny pointers, and allocations can grow and
+/// shrink in place if the following block is free.
+///
+/// Like [`Arena`](crate::arena::Arena), the allocator is meant to be the static that the
+/// address space is declared on:
+///
+/// ```
+/// # use tinyptr::{boxed::Box, tlsf::Tlsf};
+/// static HEAP: Tlsf<Heap, 0x8000> = Tlsf::new();
+/// tinyptr::address_space!(pub Heap = static HEAP);
+///
+/// let b: Box<u32, _, Heap> = Box::new_in(42, &HEAP);
+/// ```
+///
+/// The largest block is 2 MiB or more, depending on the header alignment; the rest of larger
+/// regions is unused. If the null offset is inside of the region, only the larger part on
+/// either side of it is used.
+#[repr(C)]
+pub struct Tlsf<S: AddressSpace, const SIZE: usize> {
+    region: UnsafeCell<[u8; SIZE]>,
+    control: UnsafeCell<Control<S>>,
+    initialized: AtomicBool,
+    lock: Lock,
+}
+
+// SAFE: the control structure and block headers are only accessed while holding the lock
+unsafe impl<S: AddressSpace, const SIZE: usize> Sync for Tlsf<S, SIZE> {}
+unsafe impl<S: AddressSpace, const SIZE: usize> Send for Tlsf<S, SIZE> {}
+
+impl<S: AddressSpace> Control<S> {
+    /// Alignment of block headers and payloads
+    const ALIGN: usize = {
+        let align = core::mem::align_of::<Block<S>>();
+        if align > S::GRANULE {
+            align
+        } else {
+            S::GRANULE
+        }
+    };
+    const HEADER: usize = core::mem::offset_of!(Block<S>, next_free).next_multiple_of(Self::ALIGN);
+    /// The smallest payload, large enough for the free list links
+    const MIN_PAYLOAD: usize = {
+        let links = core::mem::size_of::<Block<S>>() - Self::HEADER;
+        if links > Self::ALIGN {
+            links.next_multiple_of(Self::ALIGN)
+        } else {
+            Self::ALIGN
+        }
+    };
+    const FL_SHIFT: u32 = SL_LOG + Self::ALIGN.trailing_zeros();
+    /// Blocks smaller than this are all in the first first level list
+    const SMALL: usize = 1 << Self::FL_SHIFT;
+    const MAX_PAYLOAD: usize = (1 << (Self::FL_SHIFT + FL as u32 - 1)) - Self::ALIGN;
+
+    const fn new() -> Self {
+        Self {
+            fl_bitmap: 0,
+            sl_bitmap: [0; FL],
+            heads: [[None; SL]; FL],
+            first: None,
+            counters: Counters::new(),
+        }
+    }
+
+    fn mapping(size: usize) -> (usize, usize) {
+        if size < Self::SMALL {
+            (0, size >> Self::ALIGN.trailing_zeros())
+        } else {
+            let f = size.ilog2();
+            let sl = (size >> (f - SL_LOG)) ^ SL;
+            ((f - Self::FL_SHIFT + 1) as usize, sl)
+        }
+    }
+
+    /// Finds a list whose blocks are all at least `size` bytes large
+    fn find(&self, size: usize) -> Option<(usize, usize)> {
+        let size = if size < Self::SMALL {
+            size
+        } else {
+            size.checked_add((1 << (size.ilog2() - SL_LOG)) - 1)?
+        };
+        let (fl, sl) = Self::mapping(size);
+        if fl >= FL {
+            return None;
+        }
+        let sl_map = self.sl_bitmap[fl] & (!0 << sl);
+        if sl_map != 0 {
+            return Some((fl, sl_map.trailing_zeros() as usize));
+        }
+        let fl_map = self.fl_bitmap & (!0u32).checked_shl(fl as u32 + 1).unwrap_or(0);
+        if fl_map == 0 {
+            return None;
+        }
+        let fl = fl_map.trailing_zeros() as usize;
+        Some((fl, self.sl_bitmap[fl].trailing_zeros() as usize))
+    }
+
+    /// # Safety
+    /// The block has to be a free block that isn't in a free list.
+    unsafe fn insert(&mut self, block: *mut Block<S>) {
+        let (fl, sl) = Self::mapping(block_size(block));
+        let head = self.heads[fl][sl];
+        (*block).next_free = head;
+        (*block).prev_free = None;
+        if let Some(head) = head {
+            (*block_ptr(head)).prev_free = link(block);
+        }
+        self.heads[fl][sl] = link(block);
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmap[fl] |= 1 << sl;
+    }
+
+    /// # Safety
+    /// The block has to be in a free list.
+    unsafe fn remove(&mut self, block: *mut Block<S>) {
+        let (fl, sl) = Self::mapping(block_size(block));
+        let (next, prev) = ((*block).next_free, (*block).prev_free);
+        if let Some(next) = next {
+            (*block_ptr(next)).prev_free = prev;
+        }
+        match prev {
+            Some(prev) => (*block_ptr(prev)).next_free = next,
+            None => {
+                self.heads[fl][sl] = next;
+                if next.is_none() {
+                    self.sl_bitmap[fl] &= !(1 << sl);
+                    if self.sl_bitmap[fl] == 0 {
+                        self.fl_bitmap &= !(1 << fl);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Splits the end of a block off if it is larger than `size`, and frees it
+    ///
+    /// # Safety
+    /// The block has to be allocated or removed from its free list.
+    unsafe fn split(&mut self, block: *mut Block<S>, size: usize) {
+        let rest = block_size(block) - size;
+        if rest < Self::HEADER + Self::MIN_PAYLOAD {
+            return;
+        }
+        (*block).size = size | ((*block).size & FREE);
+        let tail = next_phys(block);
+        (*tail).prev_phys = link(block);
+        (*tail).size = (rest - Self::HEADER) | FREE;
+        self.merge_next(tail);
+        self.insert(tail);
+    }
+
+    /// Merges the block with the next block if that is free
+    ///
+    /// # Safety
+    /// The block must not be in a free list.
+    unsafe fn merge_next(&mut self, block: *mut Block<S>) {
+        let next = next_phys(block);
+        if is_free(next) {
+            self.remove(next);
+            (*block).size += Self::HEADER + block_size(next);
+        }
+        (*next_phys(block)).prev_phys = link(block);
+    }
+}
+
+fn block_ptr<S: AddressSpace>(link: NonNull<Block<S>, S>) -> *mut Block<S> {
+    link.as_ptr().as_wide_ptr()
+}
+
+fn link<S: AddressSpace>(block: *mut Block<S>) -> Link<S> {
+    NonNull::new(MutPtr::new(block)?)
+}
+
+unsafe fn block_size<S: AddressSpace>(block: *mut Block<S>) -> usize {
+    (*block).size & !FREE
+}
+
+unsafe fn is_free<S: AddressSpace>(block: *mut Block<S>) -> bool {
+    (*block).size & FREE != 0
+}
+
+unsafe fn payload<S: AddressSpace>(block: *mut Block<S>) -> *mut u8 {
+    block.cast::<u8>().add(Control::<S>::HEADER)
+}
+
+unsafe fn header_of<S: AddressSpace>(payload: *mut u8) -> *mut Block<S> {
+    payload.sub(Control::<S>::HEADER).cast()
+}
+
+unsafe fn next_phys<S: AddressSpace>(block: *mut Block<S>) -> *mut Block<S> {
+    payload(block).add(block_size(block)).cast()
+}
+
+impl<S: AddressSpace, const SIZE: usize> Tlsf<S, SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            region: UnsafeCell::new([0; SIZE]),
+            control: UnsafeCell::new(Control::new()),
+            initialized: AtomicBool::new(false),
+            lock: Lock::new(),
+        }
+    }
+
+    /// Creates the initial free block and the used sentinel block at the end of the region
+    unsafe fn init(&self, control: &mut Control<S>) {
+        let align = Control::<S>::ALIGN;
+        let header = Control::<S>::HEADER;
+        let region = self.region.get().cast::<u8>();
+        let lo = region.addr().next_multiple_of(align);
+        let hi = (region.addr() + SIZE) & !(align - 1);
+        let Some((mut lo, mut hi)) = clip::encodable::<S>(lo, hi, align) else {
+            return;
+        };
+        if let Some(null) = S::Null::null().map(S::decode) {
+            let null = null & !(align - 1);
+            if (lo..hi).contains(&null) {
+                if null - lo > hi - (null + align) {
+                    hi = null;
+                } else {
+                    lo = null + align;
+                }
+            }
+        }
+        if hi < lo + 2 * header + Control::<S>::MIN_PAYLOAD {
+            return;
+        }
+        let size = (hi - lo - 2 * header).min(Control::<S>::MAX_PAYLOAD);
+        let block = region.with_addr(lo).cast::<Block<S>>();
+        (*block).prev_phys = None;
+        (*block).size = size | FREE;
+        let sentinel = next_phys(block);
+        (*sentinel).prev_phys = link(block);
+        (*sentinel).size = 0;
+        control.first = Some(lo - region.addr());
+        control.insert(block);
+    }
+
+    /// Locks the allocator, initializing it on first use
+    fn lock(&self) -> Guard<'_> {
+        let guard = self.lock.lock();
+        // The flag is only accessed while the lock is held
+        if !self.initialized.load(Ordering::Relaxed) {
+            self.initialized.store(true, Ordering::Relaxed);
+            // SAFE: the lock is held, and the region is only used by the allocator
+            unsafe { self.init(&mut *self.control.get()) };
+        }
+        guard
+    }
+
+    /// Calls `f` with an iterator over all blocks, in address order
+    ///
+    /// The allocator is locked while `f` runs, so allocating from it in `f` deadlocks, or panics
+    /// with the `critical-section` feature.
+    pub fn blocks<R>(&self, f: impl FnOnce(Blocks<'_, S>) -> R) -> R {
+        let _guard = self.lock();
+        // SAFE: the lock is held until the iterator is gone
+        f(unsafe { self.blocks_unlocked() })
+    }
+
+    /// # Safety
+    /// The lock must be held while the iterator is used
+    unsafe fn blocks_unlocked(&self) -> Blocks<'_, S> {
+        let first = (*self.control.get()).first;
+        Blocks {
+            next: first.map_or(core::ptr::null_mut(), |first| {
+                self.region.get().cast::<u8>().wrapping_add(first).cast()
+            }),
+            _control: PhantomData,
+        }
+    }
+
+    /// Returns a snapshot of the allocator statistics
+    pub fn stats(&self) -> HeapStats {
+        let (mut free, mut largest_free) = (0, 0);
+        let _guard = self.lock();
+        // SAFE: the lock is held
+        for (_, layout, state) in unsafe { self.blocks_unlocked() } {
+            if state == BlockState::Free {
+                free += layout.size();
+                largest_free = largest_free.max(layout.size());
+            }
+        }
+        // SAFE: the lock is held
+        unsafe { (*self.control.get()).counters.snapshot(free, largest_free) }
+    }
+
+    fn payload_size(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(Control::<S>::MIN_PAYLOAD);
+        size.checked_next_multiple_of(Control::<S>::ALIGN)
+    }
+}
+
+impl<S: AddressSpace, const SIZE: usize> Default for Tlsf<S, SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<S: AddressSpace, const SIZE: usize> Allocator for &Tlsf<S, SIZE> {
+    fn allocate(&self, layout: Layout) -> Result<core::ptr::NonNull<[u8]>, AllocError> {
+        let size = Tlsf::<S, SIZE>::payload_size(layout).ok_or(AllocError)?;
+        let header = Control::<S>::HEADER;
+        let min_block = header + Control::<S>::MIN_PAYLOAD;
+        let overaligned = layout.align() > Control::<S>::ALIGN;
+        // Over-aligned allocations leave room for a free block in front of the payload
+        let request = if overaligned {
+            size.checked_add(layout.align() + min_block)
+                .ok_or(AllocError)?
+        } else {
+            size
+        };
+        let _guard = self.lock();
+        // SAFE: the lock is held
+        let control = unsafe { &mut *self.control.get() };
+        let (fl, sl) = control.find(request).ok_or(AllocError)?;
+        // SAFE: all blocks are inside of the region, and the lock is held
+        unsafe {
+            let mut block = block_ptr(control.heads[fl][sl].unwrap_unchecked());
+            control.remove(block);
+            let start = payload(block).addr();
+            if overaligned && !start.is_multiple_of(layout.align()) {
+                let aligned = (start + min_block).next_multiple_of(layout.align());
+                let front = block;
+                block = header_of(payload(front).add(aligned - start));
+                (*block).prev_phys = link(front);
+                (*block).size = block_size(front) - (aligned - start);
+                (*front).size = (aligned - start - header) | FREE;
+                (*next_phys(block)).prev_phys = link(block);
+                control.insert(front);
+            }
+            (*block).size &= !FREE;
+            control.split(block, size);
+            control.counters.allocate(block_size(block));
+            let ptr = core::ptr::NonNull::new_unchecked(payload(block));
+            Ok(core::ptr::NonNull::slice_from_raw_parts(
+                ptr,
+                block_size(block),
+            ))
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: core::ptr::NonNull<u8>, _layout: Layout) {
+        let _guard = self.lock();
+        let control = &mut *self.control.get();
+        let mut block = header_of::<S>(ptr.as_ptr());
+        control.counters.deallocate(block_size(block));
+        (*block).size |= FREE;
+        control.merge_next(block);
+        if let Some(prev) = (*block).prev_phys.map(block_ptr) {
+            if is_free(prev) {
+                control.remove(prev);
+                (*prev).size += Control::<S>::HEADER + block_size(block);
+                block = prev;
+                (*next_phys(block)).prev_phys = link(block);
+            }
+        }
+        control.insert(block);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: core::ptr::NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<core::ptr::NonNull<[u8]>, AllocError> {
+        let size = Tlsf::<S, SIZE>::payload_size(new_layout).ok_or(AllocError)?;
+        if ptr.as_ptr().addr().is_multiple_of(new_layout.align()) {
+            let _guard = self.lock();
+            let control = &mut *self.control.get();
+            let block = header_of::<S>(ptr.as_ptr());
+            let old_size = block_size(block);
+            let next = next_phys(block);
+            if block_size(block) < size
+                && is_free(next)
+                && block_size(block) + Control::<S>::HEADER + block_size(next) >= size
+            {
+                control.merge_next(block);
+            }
+            if block_size(block) >= size {
+                control.split(block, size);
+                control.counters.deallocate(old_size);
+                control.counters.allocate(block_size(block));
+                return Ok(core::ptr::NonNull::slice_from_raw_parts(
+                    ptr,
+                    block_size(block),
+                ));
+            }
+        }
+        let new = self.allocate(new_layout)?;
+        core::ptr::copy_nonoverlapping(ptr.as_ptr(), new.cast().as_ptr(), old_layout.size());
+        self.deallocate(ptr, old_layout);
+        Ok(new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: core::ptr::NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<core::ptr::NonNull<[u8]>, AllocError> {
+        if !ptr.as_ptr().addr().is_multiple_of(new_layout.align()) {
+            let new = self.allocate(new_layout)?;
+            core::ptr::copy_nonoverlapping(ptr.as_ptr(), new.cast().as_ptr(), new_layout.size());
+            self.deallocate(ptr, old_layout);
+            return Ok(new);
+        }
+        let size = Tlsf::<S, SIZE>::payload_size(new_layout).ok_or(AllocError)?;
+        let _guard = self.lock();
+        let control = &mut *self.control.get();
+        let block = header_of::<S>(ptr.as_ptr());
+        let old_size = block_size(block);
+        control.split(block, size);
+        control.counters.deallocate(old_size);
+        control.counters.allocate(block_size(block));
+        Ok(core::ptr::NonNull::slice_from_raw_parts(
+            ptr,
+            block_size(block),
+        ))
+    }
+}
+
+/// An iterator over the blocks of a [`Tlsf`] allocator, see [`Tlsf::blocks`]
+///
+/// It yields the payload, size and state of every block. The layout of used blocks is the size of
+/// the block, which can be larger than the allocation.
+pub struct Blocks<'a, S: AddressSpace> {
+    next: *mut Block<S>,
+    _control: PhantomData<&'a Control<S>>,
+}
+
+impl<S: AddressSpace> Iterator for Blocks<'_, S> {
+    type Item = (NonNull<u8, S>, Layout, BlockState);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let block = self.next;
+        if block.is_null() {
+            return None;
+        }
+        // SAFE: the lock is held, and the blocks are chained up to the sentinel
+        unsafe {
+            let size = block_size(block);
+            let state = if is_free(block) {
+                BlockState::Free
+            } else {
+                BlockState::Used
+            };
+            if size == 0 && state == BlockState::Used {
+                self.next = core::ptr::null_mut();
+                return None;
+            }
+            self.next = next_phys(block);
+            let ptr = NonNull::new(MutPtr::new(payload(block))?)?;
+            let layout = Layout::from_size_align_unchecked(size, Control::<S>::ALIGN);
+            Some((ptr, layout, state))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::{Allocator, Layout};
+
+    use super::Tlsf;
+    use crate::{boxed::Box, stats::BlockState};
+
+    #[test]
+    fn split_and_merge() {
+        static HEAP: Tlsf<Heap, 0x2000> = Tlsf::new();
+        crate::address_space!(Heap: u16 = static HEAP);
+
+        let free = HEAP.stats().free;
+        let a: Box<[u8; 100], _, Heap> = Box::new_in([1; 100], &HEAP);
+        let b: Box<[u8; 300], _, Heap> = Box::new_in([2; 300], &HEAP);
+        let c: Box<u32, _, Heap> = Box::new_in(3, &HEAP);
+        assert_eq!(HEAP.stats().in_use, 416);
+        drop(b);
+        let states: Vec<_> = HEAP.blocks(|blocks| blocks.map(|(_, _, state)| state).collect());
+        assert_eq!(
+            states,
+            [
+                BlockState::Used,
+                BlockState::Free,
+                BlockState::Used,
+                BlockState::Free
+            ]
+        );
+        // The freed block fits the next allocation of the same size
+        let b: Box<[u8; 300], _, Heap> = Box::new_in([4; 300], &HEAP);
+        assert_eq!(HEAP.blocks(|blocks| blocks.count()), 4);
+        assert_eq!((a[0], b[0], *c), (1, 4, 3));
+        drop((a, b, c));
+        let stats = HEAP.stats();
+        assert_eq!((stats.free, stats.largest_free), (free, free));
+        assert_eq!(HEAP.blocks(|blocks| blocks.count()), 1);
+    }
+
+    #[test]
+    fn alignment() {
+        static HEAP: Tlsf<Heap, 0x2000> = Tlsf::new();
+        crate::address_space!(Heap: u16 = static HEAP);
+
+        for align in [1, 8, 64, 256] {
+            let layout = Layout::from_size_align(24, align).unwrap();
+            let ptr = (&HEAP).allocate(layout).unwrap().cast::<u8>();
+            assert_eq!(ptr.as_ptr().addr() % align, 0);
+            // SAFE: the block was allocated with the same layout
+            unsafe { (&HEAP).deallocate(ptr, layout) };
+        }
+        assert_eq!(HEAP.stats().in_use, 0);
+        assert!((&HEAP).allocate(Layout::new::<[u8; 0x2000]>()).is_err());
+    }
+
+    #[test]
+    fn clips_to_the_address_space() {
+        static HEAP: Tlsf<Heap, 1024> = Tlsf::new();
+        crate::address_space!(Heap: u8 = static HEAP);
+
+        let stats = HEAP.stats();
+        assert!(stats.free > 0 && stats.free < 256);
+        HEAP.blocks(|mut blocks| {
+            assert!(blocks.all(|(ptr, layout, _)| ptr.addr() as usize + layout.size() <= 256));
+        });
+        let b: Box<[u8; 64], _, Heap> = Box::new_in([1; 64], &HEAP);
+        assert_eq!(b[63], 1);
+    }
+
+    #[test]
+    fn deallocate_with_returned_size() {
+        static HEAP: Tlsf<Heap, 0x1000> = Tlsf::new();
+        crate::address_space!(Heap: u16 = static HEAP);
+
+        let before = HEAP.stats();
+        let block = (&HEAP).allocate(Layout::new::<[u8; 5]>()).unwrap();
+        assert!(block.len() > 5);
+        let layout = Layout::from_size_align(block.len(), 1).unwrap();
+        // SAFE: the layout fits the returned block
+        unsafe { (&HEAP).deallocate(block.cast(), layout) };
+        assert_eq!(HEAP.stats().allocations, before.allocations);
+        assert_eq!(HEAP.stats().in_use, 0);
+    }
+}
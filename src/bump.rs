@@ -0,0 +1,138 @@
+//! A bump allocator that hands out references into a region of an address space
+use core::{cell::Cell, marker::PhantomData, ptr::Pointee};
+
+use crate::{
+    ptr::{MutPtr, NonNull},
+    space::AddressSpace,
+    util::IntoTiny,
+    RefMut,
+};
+
+/// A bump allocator over a region of an address space
+///
+/// Values are placed one after the other, and the returned references borrow the allocator, so
+/// all of them have to be gone before the memory is freed with [`reset`](Bump::reset):
+///
+/// ```
+/// # use core::cell::UnsafeCell;
+/// # use tinyptr::bump::Bump;
+/// struct Scratch(UnsafeCell<[u8; 1024]>);
+/// unsafe impl Sync for Scratch {}
+///
+/// static SCRATCH: Scratch = Scratch(UnsafeCell::new([0; 1024]));
+/// tinyptr::address_space!(pub Heap = static SCRATCH);
+///
+/// // SAFE: the buffer is the arena of `Heap` and only used by the bump allocator
+/// let mut scratch = unsafe { Bump::<Heap>::new(&mut *SCRATCH.0.get()) };
+/// let a = scratch.alloc(1u32);
+/// let b = scratch.alloc([0u8; 16]);
+/// # assert_eq!(*a, 1);
+/// # drop((a, b));
+/// scratch.reset();
+/// ```
+///
+/// The destructors of allocated values are never run.
+pub struct Bump<'r, S: AddressSpace> {
+    region: *mut u8,
+    len: usize,
+    used: Cell<usize>,
+    _phantom: PhantomData<(&'r mut [u8], S)>,
+}
+
+impl<'r, S: AddressSpace> Bump<'r, S> {
+    /// Creates a bump allocator that allocates from `region`
+    ///
+    /// Parts of the region that can't be encoded in `S` are skipped.
+    ///
+    /// # Safety
+    /// The returned references are decoded through [`AddressSpace::root`], so the region has to
+    /// be part of the allocation that the root of `S` points into, for example a part of the
+    /// static that the address space is declared on.
+    pub unsafe fn new(region: &'r mut [u8]) -> Self {
+        Self {
+            region: region.as_mut_ptr(),
+            len: region.len(),
+            used: Cell::new(0),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Moves a value into the region, returning it back if there is no space left
+    pub fn try_alloc<T>(&self, value: T) -> Result<RefMut<'_, T, S>, T>
+    where
+        <T as Pointee>::Metadata: IntoTiny<S>,
+    {
+        let align = core::mem::align_of::<T>().max(S::GRANULE);
+        let size = core::mem::size_of::<T>();
+        let end = self.region.addr() + self.len;
+        let mut start = (self.region.addr() + self.used.get()).next_multiple_of(align);
+        loop {
+            if start + size > end {
+                return Err(value);
+            }
+            let ptr = self.region.with_addr(start).cast::<T>();
+            // Skips addresses that can't be encoded, like the null offset
+            if let Some(tiny) = MutPtr::new(ptr).and_then(NonNull::new) {
+                self.used.set(start + size - self.region.addr());
+                // SAFE: the memory is inside of the region and hasn't been handed out yet. It is
+                // written through the decoded pointer, like the returned reference.
+                unsafe {
+                    tiny.as_ptr().write(value);
+                    return Ok(RefMut::new(tiny));
+                }
+            }
+            start += align;
+        }
+    }
+
+    /// Moves a value into the region
+    ///
+    /// # Panics
+    /// This function panics if there is no space left.
+    pub fn alloc<T>(&self, value: T) -> RefMut<'_, T, S>
+    where
+        <T as Pointee>::Metadata: IntoTiny<S>,
+    {
+        match self.try_alloc(value) {
+            Ok(r) => r,
+            Err(_) => panic!("bump allocator out of memory"),
+        }
+    }
+
+    /// Returns the number of bytes that have been allocated, including padding
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    /// Frees all allocations
+    pub fn reset(&mut self) {
+        self.used.set(0);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::Bump;
+    use crate::space::AddressSpace;
+
+    crate::host_space!(Heap: u8 = 64);
+
+    #[test]
+    fn alloc_and_reset() {
+        // SAFE: the buffer of the host arena is not used for anything else
+        let region = unsafe { core::slice::from_raw_parts_mut(Heap::root(), 64) };
+        let mut bump = unsafe { Bump::<Heap>::new(region) };
+        let a = bump.alloc(1u8);
+        let mut b = bump.alloc(2u32);
+        *b += 1;
+        // The null offset is skipped
+        assert_ne!((&raw const *a).addr(), Heap::base());
+        assert_eq!((&raw const *b).addr() % 4, 0);
+        assert_eq!((*a, *b), (1, 3));
+        assert_eq!(bump.try_alloc([0u8; 64]).err(), Some([0; 64]));
+        assert_eq!(bump.used(), 8);
+        bump.reset();
+        assert_eq!(bump.used(), 0);
+        assert_eq!(*bump.alloc([7u8; 60]), [7; 60]);
+    }
+}
@@ -1,18 +1,28 @@
 //! Small Pointer support crate
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![cfg_attr(feature = "alloc", feature(allocator_api))]
-#![feature(mixed_integer_ops)]
-#![feature(ptr_internals)]
+#![cfg_attr(feature = "validate", feature(layout_for_ptr))]
+#![feature(associated_type_defaults)]
 #![feature(ptr_metadata)]
+#![feature(strict_provenance_lints)]
+#![feature(unsize)]
+#![deny(fuzzy_provenance_casts, lossy_provenance_casts)]
 
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
 #[cfg(feature = "alloc")]
 mod alloc_integration;
+pub mod bump;
+pub mod offset;
 pub mod ptr;
 mod reference;
+pub mod space;
+#[cfg(all(feature = "alloc", any(test, feature = "test-support")))]
+pub mod test_support;
 pub mod util;
+#[cfg(feature = "validate")]
+pub mod validate;
 
 #[cfg(feature = "alloc")]
 #[doc(inline)]
@@ -20,37 +30,88 @@ pub use alloc_integration::*;
 #[doc(inline)]
 pub use reference::*;
 
-pub type TinyUSize = u16;
+use space::{AddressSpace, NullEncoding};
 
-/// Converts a pointer to an u16 without checking for any invariants.
+/// The type that slice lengths are compressed to in an address space
+pub type TinyUSize<S> = <S as AddressSpace>::Len;
+
+/// Converts a pointer to an offset without checking for any invariants.
 ///
 /// # Safety
 /// This function is unsafe because it does not do any range checking.
 ///
-/// - The caller has to ensure that ptr points into the ram arena, or is a null pointer
-pub unsafe fn ptr_to_u16_unchecked<const BASE_ADDR: usize>(ptr: *const ()) -> u16 {
+/// - The caller has to ensure that ptr points into the ram arena, or is a null pointer in an
+///   address space that has one
+pub unsafe fn ptr_to_offset_unchecked<S: AddressSpace>(ptr: *const ()) -> S::Offset {
     if ptr.is_null() {
-        return 0;
+        return S::Null::null().unwrap_unchecked();
     }
-    (ptr as usize - BASE_ADDR) as u16
+    S::encode_unchecked(ptr.addr())
 }
 
-/// Converts a pointer to an u16.
-pub fn ptr_to_u16<const BASE_ADDR: usize>(ptr: *const ()) -> Option<u16> {
+/// Converts a pointer to an offset.
+///
+/// Returns `None` if the pointer is outside of the address space, or if it is a null pointer and
+/// the address space has none.
+pub fn ptr_to_offset<S: AddressSpace>(ptr: *const ()) -> Option<S::Offset> {
     if ptr.is_null() {
-        return Some(0);
+        return S::Null::null();
     }
-    let ptr_usize = ptr as usize;
-    if ptr_usize <= BASE_ADDR || ptr_usize >= BASE_ADDR + 65536 {
+    let offset = S::encode(ptr.addr())?;
+    if S::Null::is_null(offset) {
         return None;
     }
-    Some((ptr_usize - BASE_ADDR) as u16)
+    Some(offset)
 }
 
-/// Converts a u16 to a pointer
-pub fn u16_to_ptr<const BASE_ADDR: usize>(ptr: u16) -> *const () {
-    if ptr == 0 {
+/// Converts an offset to a pointer
+///
+/// The pointer derives its provenance from the address space, see
+/// [`decode_ptr`](AddressSpace::decode_ptr).
+pub fn offset_to_ptr<S: AddressSpace>(ptr: S::Offset) -> *const () {
+    if S::Null::is_null(ptr) {
         return core::ptr::null();
     }
-    (ptr as usize + BASE_ADDR) as *const ()
+    S::decode_ptr(ptr).cast_const().cast()
+}
+
+/// Offsets a pointer by `count` elements of `size` bytes.
+///
+/// Returns `None` if the pointer is null, if the multiplication overflows, or if the result is
+/// outside of the address space or the null offset.
+pub(crate) fn checked_offset<S: AddressSpace>(
+    ptr: S::Offset,
+    count: isize,
+    size: usize,
+) -> Option<S::Offset> {
+    if S::Null::is_null(ptr) {
+        return None;
+    }
+    let bytes = count.checked_mul(size.try_into().ok()?)?;
+    let addr = S::try_decode(ptr)?.checked_add_signed(bytes)?;
+    let offset = S::encode(addr)?;
+    if S::Null::is_null(offset) {
+        return None;
+    }
+    Some(offset)
+}
+
+/// Calculates the distance between two pointers in elements of `size` bytes.
+///
+/// Returns `None` if either pointer is null, if `size` is zero, or if the distance isn't a
+/// multiple of `size`.
+pub(crate) fn checked_offset_from<S: AddressSpace>(
+    ptr: S::Offset,
+    origin: S::Offset,
+    size: usize,
+) -> Option<isize> {
+    if size == 0 || S::Null::is_null(ptr) || S::Null::is_null(origin) {
+        return None;
+    }
+    let size = isize::try_from(size).ok()?;
+    let bytes = (S::decode(ptr) as isize).checked_sub(S::decode(origin) as isize)?;
+    if bytes % size != 0 {
+        return None;
+    }
+    Some(bytes / size)
 }
@@ -0,0 +1,263 @@
+//! Storage types for compressed pointer offsets
+//!
+//! The width of the offset stored in a tiny pointer is chosen by the
+//! [`AddressSpace::Offset`](crate::space::AddressSpace::Offset) type. `u8`, `u16`, [`U24`] and
+//! `u32` are supported, covering arenas of 256 bytes up to 4 GiB.
+use core::{fmt, num::NonZero};
+
+/// An unsigned integer that a tiny pointer offset can be stored in
+pub trait Offset: Copy + Eq + Ord + fmt::Debug {
+    /// The non-zero variant of the offset, used by `NonNull`
+    type NonZero: Copy;
+
+    /// Number of bits in the offset
+    const BITS: u32;
+    /// The zero offset
+    const ZERO: Self;
+
+    /// Converts an offset, returning `None` if it doesn't fit
+    fn from_usize(v: usize) -> Option<Self>;
+    /// Converts an offset, truncating it to the width of the type
+    fn from_usize_truncate(v: usize) -> Self;
+    fn to_usize(self) -> usize;
+    /// Adds a signed value, wrapping around at the width of the type
+    fn wrapping_add_signed(self, v: isize) -> Self {
+        Self::from_usize_truncate(self.to_usize().wrapping_add_signed(v))
+    }
+
+    fn to_nonzero(self) -> Option<Self::NonZero>;
+    /// Converts the offset to a non-zero offset
+    ///
+    /// # Safety
+    /// The offset must not be zero
+    unsafe fn to_nonzero_unchecked(self) -> Self::NonZero;
+    fn from_nonzero(v: Self::NonZero) -> Self;
+}
+
+/// An offset type that the constant `V` can be converted to at compile time
+///
+/// Using [`OFFSET`](ConstOffset::OFFSET) fails to compile if `V` doesn't fit into the type, and
+/// using [`NON_ZERO`](ConstOffset::NON_ZERO) if it is zero as well.
+pub trait ConstOffset<const V: usize>: Offset {
+    const OFFSET: Self;
+    const NON_ZERO: Self::NonZero;
+}
+
+macro_rules! impl_offset {
+    ($($ty:ty),*) => {
+        $(
+            impl Offset for $ty {
+                type NonZero = NonZero<$ty>;
+
+                const BITS: u32 = <$ty>::BITS;
+                const ZERO: Self = 0;
+
+                #[inline(always)]
+                fn from_usize(v: usize) -> Option<Self> {
+                    v.try_into().ok()
+                }
+                #[inline(always)]
+                fn from_usize_truncate(v: usize) -> Self {
+                    v as $ty
+                }
+                #[inline(always)]
+                fn to_usize(self) -> usize {
+                    self as usize
+                }
+                #[inline(always)]
+                fn to_nonzero(self) -> Option<Self::NonZero> {
+                    NonZero::new(self)
+                }
+                #[inline(always)]
+                unsafe fn to_nonzero_unchecked(self) -> Self::NonZero {
+                    NonZero::new_unchecked(self)
+                }
+                #[inline(always)]
+                fn from_nonzero(v: Self::NonZero) -> Self {
+                    v.get()
+                }
+            }
+
+            impl<const V: usize> ConstOffset<V> for $ty {
+                const OFFSET: Self = {
+                    assert!(V <= <$ty>::MAX as usize, "offset out of range");
+                    V as $ty
+                };
+                const NON_ZERO: Self::NonZero = match NonZero::new(<Self as ConstOffset<V>>::OFFSET) {
+                    Some(v) => v,
+                    None => panic!("offset is zero"),
+                };
+            }
+        )*
+    };
+}
+
+impl_offset!(u8, u16, u32);
+
+/// A 24 bit unsigned integer with an alignment of 1
+///
+/// There is no way to give a 3 byte type a niche, so `Option<NonNull<T, S>>` is larger than
+/// `NonNull<T, S>` for address spaces with this offset type.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(transparent)]
+pub struct U24([u8; 3]);
+
+impl U24 {
+    pub const MAX: usize = (1 << 24) - 1;
+
+    pub const fn new(v: u32) -> Option<Self> {
+        if v as usize > Self::MAX {
+            return None;
+        }
+        Some(Self::new_truncate(v))
+    }
+
+    pub const fn new_truncate(v: u32) -> Self {
+        let [a, b, c, _] = v.to_le_bytes();
+        Self([a, b, c])
+    }
+
+    pub const fn get(self) -> u32 {
+        let [a, b, c] = self.0;
+        u32::from_le_bytes([a, b, c, 0])
+    }
+}
+
+impl fmt::Debug for U24 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+impl Offset for U24 {
+    type NonZero = U24;
+
+    const BITS: u32 = 24;
+    const ZERO: Self = Self([0; 3]);
+
+    #[inline(always)]
+    fn from_usize(v: usize) -> Option<Self> {
+        Self::new(v.try_into().ok()?)
+    }
+    #[inline(always)]
+    fn from_usize_truncate(v: usize) -> Self {
+        Self::new_truncate(v as u32)
+    }
+    #[inline(always)]
+    fn to_usize(self) -> usize {
+        self.get() as usize
+    }
+    #[inline(always)]
+    fn to_nonzero(self) -> Option<Self::NonZero> {
+        if self == Self::ZERO {
+            None
+        } else {
+            Some(self)
+        }
+    }
+    #[inline(always)]
+    unsafe fn to_nonzero_unchecked(self) -> Self::NonZero {
+        self
+    }
+    #[inline(always)]
+    fn from_nonzero(v: Self::NonZero) -> Self {
+        v
+    }
+}
+
+impl<const V: usize> ConstOffset<V> for U24 {
+    const OFFSET: Self = {
+        assert!(V <= Self::MAX, "offset out of range");
+        Self::new_truncate(V as u32)
+    };
+    const NON_ZERO: Self::NonZero = {
+        assert!(V != 0, "offset is zero");
+        <Self as ConstOffset<V>>::OFFSET
+    };
+}
+
+/// Stores an offset without alignment requirements
+///
+/// Tiny pointers are normally aligned to their offset type, so a `u16` offset with a `u8` slice
+/// length takes up 4 bytes. With `Unaligned<u16>` offsets, the same pointer is 3 bytes large, at
+/// the cost of slower loads on some architectures.
+#[derive(Default)]
+#[repr(C, packed)]
+pub struct Unaligned<O>(pub O);
+
+impl<O: Copy> Unaligned<O> {
+    pub fn get(self) -> O {
+        self.0
+    }
+}
+
+impl<O: Copy> Copy for Unaligned<O> {}
+
+impl<O: Copy> Clone for Unaligned<O> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<O: Copy + PartialEq> PartialEq for Unaligned<O> {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl<O: Copy + Eq> Eq for Unaligned<O> {}
+
+impl<O: Copy + Ord> PartialOrd for Unaligned<O> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<O: Copy + Ord> Ord for Unaligned<O> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.get().cmp(&other.get())
+    }
+}
+
+impl<O: Copy + fmt::Debug> fmt::Debug for Unaligned<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+impl<O: Offset> Offset for Unaligned<O> {
+    type NonZero = Unaligned<O::NonZero>;
+
+    const BITS: u32 = O::BITS;
+    const ZERO: Self = Self(O::ZERO);
+
+    #[inline(always)]
+    fn from_usize(v: usize) -> Option<Self> {
+        O::from_usize(v).map(Self)
+    }
+    #[inline(always)]
+    fn from_usize_truncate(v: usize) -> Self {
+        Self(O::from_usize_truncate(v))
+    }
+    #[inline(always)]
+    fn to_usize(self) -> usize {
+        self.get().to_usize()
+    }
+    #[inline(always)]
+    fn to_nonzero(self) -> Option<Self::NonZero> {
+        self.get().to_nonzero().map(Unaligned)
+    }
+    #[inline(always)]
+    unsafe fn to_nonzero_unchecked(self) -> Self::NonZero {
+        Unaligned(self.get().to_nonzero_unchecked())
+    }
+    #[inline(always)]
+    fn from_nonzero(v: Self::NonZero) -> Self {
+        Self(O::from_nonzero(v.get()))
+    }
+}
+
+impl<O: ConstOffset<V>, const V: usize> ConstOffset<V> for Unaligned<O> {
+    const OFFSET: Self = Self(O::OFFSET);
+    const NON_ZERO: Self::NonZero = Unaligned(O::NON_ZERO);
+}
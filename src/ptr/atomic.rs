@@ -0,0 +1,342 @@
+use core::{marker::PhantomData, ptr::Pointee, sync::atomic::Ordering};
+
+use crate::{space::AddressSpace, util::IntoTiny};
+
+use super::MutPtr;
+
+/// An integer type with an atomic counterpart
+pub trait AtomicBits: Copy {
+    type Atomic;
+
+    fn new_atomic(v: Self) -> Self::Atomic;
+    fn into_inner(a: Self::Atomic) -> Self;
+    fn load(a: &Self::Atomic, order: Ordering) -> Self;
+    fn store(a: &Self::Atomic, v: Self, order: Ordering);
+    fn swap(a: &Self::Atomic, v: Self, order: Ordering) -> Self;
+    fn compare_exchange(
+        a: &Self::Atomic,
+        current: Self,
+        new: Self,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Self, Self>;
+    fn compare_exchange_weak(
+        a: &Self::Atomic,
+        current: Self,
+        new: Self,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Self, Self>;
+}
+
+macro_rules! impl_atomic_bits {
+    ($($width:literal: $ty:ty => $atomic:ty),*) => {
+        $(
+            #[cfg(target_has_atomic = $width)]
+            impl AtomicBits for $ty {
+                type Atomic = $atomic;
+
+                #[inline(always)]
+                fn new_atomic(v: Self) -> Self::Atomic {
+                    <$atomic>::new(v)
+                }
+                #[inline(always)]
+                fn into_inner(a: Self::Atomic) -> Self {
+                    a.into_inner()
+                }
+                #[inline(always)]
+                fn load(a: &Self::Atomic, order: Ordering) -> Self {
+                    a.load(order)
+                }
+                #[inline(always)]
+                fn store(a: &Self::Atomic, v: Self, order: Ordering) {
+                    a.store(v, order)
+                }
+                #[inline(always)]
+                fn swap(a: &Self::Atomic, v: Self, order: Ordering) -> Self {
+                    a.swap(v, order)
+                }
+                #[inline(always)]
+                fn compare_exchange(
+                    a: &Self::Atomic,
+                    current: Self,
+                    new: Self,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<Self, Self> {
+                    a.compare_exchange(current, new, success, failure)
+                }
+                #[inline(always)]
+                fn compare_exchange_weak(
+                    a: &Self::Atomic,
+                    current: Self,
+                    new: Self,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<Self, Self> {
+                    a.compare_exchange_weak(current, new, success, failure)
+                }
+            }
+        )*
+    };
+}
+
+impl_atomic_bits!(
+    "8": u8 => core::sync::atomic::AtomicU8,
+    "16": u16 => core::sync::atomic::AtomicU16,
+    "32": u32 => core::sync::atomic::AtomicU32
+);
+
+/// The offset and compressed metadata of a tiny pointer, packed into a single integer
+pub trait PackBits: Copy {
+    type Bits: AtomicBits;
+
+    fn pack(self) -> Self::Bits;
+    fn unpack(bits: Self::Bits) -> Self;
+}
+
+macro_rules! impl_pack_thin {
+    ($($width:literal: $ty:ty),*) => {
+        $(
+            #[cfg(target_has_atomic = $width)]
+            impl PackBits for ($ty, ()) {
+                type Bits = $ty;
+
+                #[inline(always)]
+                fn pack(self) -> Self::Bits {
+                    self.0
+                }
+                #[inline(always)]
+                fn unpack(bits: Self::Bits) -> Self {
+                    (bits, ())
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_pack_slice {
+    ($($offset:ty, $len:ty => $width:literal: $bits:ty),*) => {
+        $(
+            #[cfg(target_has_atomic = $width)]
+            impl PackBits for ($offset, $len) {
+                type Bits = $bits;
+
+                #[inline(always)]
+                fn pack(self) -> Self::Bits {
+                    ((self.0 as $bits) << <$len>::BITS) | self.1 as $bits
+                }
+                #[inline(always)]
+                fn unpack(bits: Self::Bits) -> Self {
+                    ((bits >> <$len>::BITS) as $offset, bits as $len)
+                }
+            }
+        )*
+    };
+}
+
+impl_pack_thin!("8": u8, "16": u16, "32": u32);
+impl_pack_slice!(
+    u8, u8 => "16": u16,
+    u8, u16 => "32": u32,
+    u16, u8 => "32": u32,
+    u16, u16 => "32": u32
+);
+
+type Parts<T, S> = (
+    <S as AddressSpace>::Offset,
+    <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny,
+);
+
+/// A tiny pointer which can be safely shared between threads and interrupt handlers
+///
+/// Thin pointers with `u16` offsets are backed by an `AtomicU16`, slice pointers with `u16`
+/// offsets and lengths by an `AtomicU32`. Comparisons in `compare_exchange` include the metadata.
+pub struct AtomicMutPtr<T, S: AddressSpace>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    Parts<T, S>: PackBits,
+{
+    bits: <<Parts<T, S> as PackBits>::Bits as AtomicBits>::Atomic,
+    _phantom: PhantomData<(*mut T, S)>,
+}
+
+unsafe impl<T, S: AddressSpace> Send for AtomicMutPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    Parts<T, S>: PackBits,
+{
+}
+
+unsafe impl<T, S: AddressSpace> Sync for AtomicMutPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    Parts<T, S>: PackBits,
+{
+}
+
+impl<T, S: AddressSpace> AtomicMutPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    Parts<T, S>: PackBits,
+{
+    fn pack(ptr: MutPtr<T, S>) -> <Parts<T, S> as PackBits>::Bits {
+        ptr.as_raw_parts().pack()
+    }
+
+    fn unpack(bits: <Parts<T, S> as PackBits>::Bits) -> MutPtr<T, S> {
+        let (ptr, metadata) = Parts::<T, S>::unpack(bits);
+        MutPtr::from_raw_parts(ptr, metadata)
+    }
+
+    pub fn new(ptr: MutPtr<T, S>) -> Self {
+        Self {
+            bits: AtomicBits::new_atomic(Self::pack(ptr)),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> MutPtr<T, S> {
+        Self::unpack(AtomicBits::into_inner(self.bits))
+    }
+
+    pub fn load(&self, order: Ordering) -> MutPtr<T, S> {
+        Self::unpack(AtomicBits::load(&self.bits, order))
+    }
+
+    pub fn store(&self, ptr: MutPtr<T, S>, order: Ordering) {
+        AtomicBits::store(&self.bits, Self::pack(ptr), order)
+    }
+
+    pub fn swap(&self, ptr: MutPtr<T, S>, order: Ordering) -> MutPtr<T, S> {
+        Self::unpack(AtomicBits::swap(&self.bits, Self::pack(ptr), order))
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: MutPtr<T, S>,
+        new: MutPtr<T, S>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<MutPtr<T, S>, MutPtr<T, S>> {
+        AtomicBits::compare_exchange(
+            &self.bits,
+            Self::pack(current),
+            Self::pack(new),
+            success,
+            failure,
+        )
+        .map(Self::unpack)
+        .map_err(Self::unpack)
+    }
+
+    pub fn compare_exchange_weak(
+        &self,
+        current: MutPtr<T, S>,
+        new: MutPtr<T, S>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<MutPtr<T, S>, MutPtr<T, S>> {
+        AtomicBits::compare_exchange_weak(
+            &self.bits,
+            Self::pack(current),
+            Self::pack(new),
+            success,
+            failure,
+        )
+        .map(Self::unpack)
+        .map_err(Self::unpack)
+    }
+
+    /// Fetches the value, and applies a function to it that returns an optional new value.
+    ///
+    /// See `core::sync::atomic::AtomicPtr::fetch_update`
+    pub fn fetch_update<F>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<MutPtr<T, S>, MutPtr<T, S>>
+    where
+        F: FnMut(MutPtr<T, S>) -> Option<MutPtr<T, S>>,
+    {
+        let mut prev = self.load(fetch_order);
+        while let Some(next) = f(prev) {
+            match self.compare_exchange_weak(prev, next, set_order, fetch_order) {
+                Ok(x) => return Ok(x),
+                Err(next_prev) => prev = next_prev,
+            }
+        }
+        Err(prev)
+    }
+}
+
+impl<T, S: AddressSpace> From<MutPtr<T, S>> for AtomicMutPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    Parts<T, S>: PackBits,
+{
+    fn from(ptr: MutPtr<T, S>) -> Self {
+        Self::new(ptr)
+    }
+}
+
+impl<T, S: AddressSpace> core::fmt::Debug for AtomicMutPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+    Parts<T, S>: PackBits,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&self.load(Ordering::Relaxed), f)
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use core::sync::atomic::Ordering;
+
+    use super::AtomicMutPtr;
+    use crate::{ptr::MutPtr, space::AddressSpace};
+
+    crate::host_space!(Heap: u16 = 256);
+
+    fn ptr(offset: usize) -> MutPtr<u32, Heap> {
+        MutPtr::new(Heap::root().wrapping_add(offset).cast()).unwrap()
+    }
+
+    #[test]
+    fn thin() {
+        let a = AtomicMutPtr::new(ptr(4));
+        assert_eq!(a.swap(ptr(8), Ordering::Relaxed), ptr(4));
+        assert_eq!(
+            a.compare_exchange(ptr(4), ptr(12), Ordering::Relaxed, Ordering::Relaxed),
+            Err(ptr(8))
+        );
+        assert_eq!(
+            a.compare_exchange(ptr(8), ptr(12), Ordering::Relaxed, Ordering::Relaxed),
+            Ok(ptr(8))
+        );
+        a.store(ptr(16), Ordering::Relaxed);
+        assert_eq!(a.into_inner(), ptr(16));
+    }
+
+    #[test]
+    fn slices() {
+        let slice = |len| {
+            let raw = core::ptr::slice_from_raw_parts_mut(Heap::root().wrapping_add(4), len);
+            MutPtr::<[u8], Heap>::new(raw).unwrap()
+        };
+        let a = AtomicMutPtr::new(slice(2));
+        // The length is part of the comparison
+        assert!(a
+            .compare_exchange(slice(3), slice(1), Ordering::Relaxed, Ordering::Relaxed)
+            .is_err());
+        assert_eq!(a.load(Ordering::Relaxed), slice(2));
+    }
+}
@@ -1,29 +1,39 @@
-use core::{fmt::Pointer, marker::PhantomData, mem::MaybeUninit, ptr::Pointee};
+use core::{
+    fmt::Pointer,
+    marker::{PhantomData, Unsize},
+    mem::MaybeUninit,
+    ptr::Pointee,
+};
 
-use crate::{util::IntoTiny, Ref, RefMut};
+use crate::{
+    offset::Offset,
+    space::{AddressSpace, NullEncoding},
+    util::IntoTiny,
+    Ref, RefMut,
+};
 
 use super::{MutPtr, NonNull};
 
-pub struct ConstPtr<T, const BASE_ADDR: usize>
+pub struct ConstPtr<T, S: AddressSpace>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
-    ptr: u16,
-    metadata: <<T as Pointee>::Metadata as IntoTiny>::Tiny,
-    _phantom: PhantomData<*const T>,
+    ptr: S::Offset,
+    metadata: <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny,
+    _phantom: PhantomData<(*const T, S)>,
 }
 
-impl<T, const BASE_ADDR: usize> ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
     pub fn new(ptr: *const T) -> Option<Self> {
         let (ptr, metadata) = ptr.to_raw_parts();
         Some(Self {
-            ptr: crate::ptr_to_u16::<BASE_ADDR>(ptr)?,
-            metadata: metadata.into_tiny()?,
+            ptr: crate::ptr_to_offset::<S>(ptr)?,
+            metadata: IntoTiny::<S>::into_tiny(metadata)?,
             _phantom: PhantomData,
         })
     }
@@ -31,40 +41,90 @@ where
     ///
     /// # Safety
     /// The caller has to ensure that the pointer points to the initialized ram buffer. They also have to ensure that the fat pointer metadata can be represented in a compressed form
+    #[cfg_attr(feature = "validate", track_caller)]
     pub unsafe fn new_unchecked(ptr: *const T) -> Self {
+        #[cfg(feature = "validate")]
+        crate::validate::check_ptr::<T, S>(ptr);
         let (ptr, metadata) = ptr.to_raw_parts();
         Self {
-            ptr: crate::ptr_to_u16_unchecked::<BASE_ADDR>(ptr),
-            metadata: metadata.into_tiny_unchecked(),
+            ptr: crate::ptr_to_offset_unchecked::<S>(ptr),
+            metadata: IntoTiny::<S>::into_tiny_unchecked(metadata),
             _phantom: PhantomData,
         }
     }
+    #[cfg_attr(feature = "validate", track_caller)]
     pub fn as_wide_ptr(self) -> *const T {
+        #[cfg(feature = "validate")]
+        crate::validate::check_offset::<S>(self.ptr);
         // SAFE: this function can't be called without the ram buffer being initialized
-        let ptr = crate::u16_to_ptr::<BASE_ADDR>(self.ptr);
+        let ptr = crate::offset_to_ptr::<S>(self.ptr);
         core::ptr::from_raw_parts(
             ptr,
-            <<T as Pointee>::Metadata as IntoTiny>::from_tiny(self.metadata),
+            <<T as Pointee>::Metadata as IntoTiny<S>>::from_tiny(self.metadata),
         )
     }
     pub fn is_null(self) -> bool {
-        self.ptr == 0
+        S::Null::is_null(self.ptr)
     }
-    pub fn cast<U>(self) -> ConstPtr<U, BASE_ADDR>
+    /// Casts to a pointer of another type
+    ///
+    /// The address is unchanged, so this is lossless even in address spaces with shifted offsets.
+    /// Pointer arithmetic on the result is only possible if the size of `U` is a multiple of the
+    /// granule of the address space.
+    pub fn cast<U>(self) -> ConstPtr<U, S>
     where
-        <U as Pointee>::Metadata: IntoTiny,
+        <U as Pointee>::Metadata: IntoTiny<S>,
     {
         // SAFE: We know from the trait bounds and the fact that it comes from a valid pointer that this function is safe
         unsafe { ConstPtr::new_unchecked(self.as_wide_ptr().cast()) }
     }
-    pub fn as_raw_parts(self) -> (u16, <<T as Pointee>::Metadata as IntoTiny>::Tiny) {
+    /// Converts to a pointer to an unsized type, for example `[T; N]` to `[T]` or `T` to
+    /// `dyn Trait`
+    ///
+    /// Returns `None` if the new metadata can't be compressed, for example if the vtable is not
+    /// part of the vtable index of the address space.
+    pub fn unsize<U>(self) -> Option<ConstPtr<U, S>>
+    where
+        T: Unsize<U>,
+        U: ?Sized,
+        <U as Pointee>::Metadata: IntoTiny<S>,
+    {
+        let ptr: *const U = self.as_wide_ptr();
+        let (_, metadata) = ptr.to_raw_parts();
+        Some(ConstPtr::from_raw_parts(
+            self.ptr,
+            IntoTiny::<S>::into_tiny(metadata)?,
+        ))
+    }
+    /// Changes the mutability of the pointer
+    pub const fn cast_mut(self) -> MutPtr<T, S> {
+        MutPtr::from_raw_parts(self.ptr, self.metadata)
+    }
+    /// Returns the offset of the pointer
+    ///
+    /// Tiny pointers don't carry provenance themselves, decoded pointers derive it from the
+    /// [root](AddressSpace::root) of the address space.
+    pub fn addr(self) -> S::Offset {
+        self.ptr
+    }
+    /// Creates a pointer with the given offset, keeping the metadata
+    pub fn with_addr(mut self, addr: S::Offset) -> Self {
+        self.ptr = addr;
+        self
+    }
+    /// Creates a pointer by mapping the offset, keeping the metadata
+    pub fn map_addr(self, f: impl FnOnce(S::Offset) -> S::Offset) -> Self {
+        let addr = f(self.ptr);
+        self.with_addr(addr)
+    }
+    pub fn as_raw_parts(self) -> (S::Offset, <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny) {
         (self.ptr, self.metadata)
     }
     /// Returns a shared reference to the pointer destination
     ///
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_ref<'a>(self) -> Option<Ref<'a, T, BASE_ADDR>> {
+    pub unsafe fn as_ref<'a>(self) -> Option<Ref<'a, T, S>> {
         if self.is_null() {
             None
         } else {
@@ -75,13 +135,13 @@ where
     ///
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_ref_unchecked<'a>(self) -> Ref<'a, T, BASE_ADDR> {
+    pub unsafe fn as_ref_unchecked<'a>(self) -> Ref<'a, T, S> {
         Ref::new(NonNull::new_unchecked(self.into()))
     }
     /// Returns a shared reference to a MaybeUninit
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_uninit_ref<'a>(self) -> Option<Ref<'a, MaybeUninit<T>, BASE_ADDR>>
+    pub unsafe fn as_uninit_ref<'a>(self) -> Option<Ref<'a, MaybeUninit<T>, S>>
     where
         T: Sized,
     {
@@ -90,7 +150,7 @@ where
     /// Returns a shared reference to a MaybeUninit, without checking for null
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_uninit_ref_unchecked<'a>(self) -> Ref<'a, MaybeUninit<T>, BASE_ADDR>
+    pub unsafe fn as_uninit_ref_unchecked<'a>(self) -> Ref<'a, MaybeUninit<T>, S>
     where
         T: Sized,
     {
@@ -100,20 +160,31 @@ where
     ///
     /// # Safety
     /// This function has undefined behaviour if count or the resulting pointer overflows
+    #[cfg_attr(feature = "validate", track_caller)]
     pub unsafe fn offset(self, count: i16) -> Self
     where
         T: Sized,
     {
+        #[cfg(feature = "validate")]
+        crate::validate::check_arithmetic::<S>(self.ptr, count as isize, core::mem::size_of::<T>());
         self.wrapping_offset(count)
     }
     /// Calculates the offset from a pointer, wrapping if count or the pointer overflows
+    ///
+    /// In address spaces with shifted offsets, the size of `T` has to be a multiple of the
+    /// granule of the address space.
     pub fn wrapping_offset(mut self, count: i16) -> Self
     where
         T: Sized,
     {
-        self.ptr = self
-            .ptr
-            .wrapping_add_signed(count.wrapping_mul(core::mem::size_of::<T>() as i16));
+        const {
+            assert!(
+                core::mem::size_of::<T>().is_multiple_of(S::GRANULE),
+                "size of T is not a multiple of the address space granule"
+            )
+        };
+        let bytes = (count as isize).wrapping_mul(core::mem::size_of::<T>() as isize);
+        self.ptr = self.ptr.wrapping_add_signed(bytes >> S::SHIFT);
         self
     }
     /// Calculates the offset between two pointers
@@ -163,7 +234,50 @@ where
         self.wrapping_offset((count as i16).wrapping_neg())
     }
 
-    pub fn set_ptr_value(mut self, val: ConstPtr<u8, BASE_ADDR>) -> Self {
+    /// Calculates the offset from a pointer, checking that the result stays in the address space
+    ///
+    /// Returns `None` if the pointer is null, if `count * size_of::<T>()` overflows, or if the
+    /// result is outside of the address space or the null pointer.
+    pub fn checked_offset(mut self, count: isize) -> Option<Self>
+    where
+        T: Sized,
+    {
+        self.ptr = crate::checked_offset::<S>(self.ptr, count, core::mem::size_of::<T>())?;
+        Some(self)
+    }
+
+    /// Checked version of `add`, see [`checked_offset`](Self::checked_offset)
+    pub fn checked_add(mut self, count: usize) -> Option<Self>
+    where
+        T: Sized,
+    {
+        let count = isize::try_from(count).ok()?;
+        self.ptr = crate::checked_offset::<S>(self.ptr, count, core::mem::size_of::<T>())?;
+        Some(self)
+    }
+
+    /// Checked version of `sub`, see [`checked_offset`](Self::checked_offset)
+    pub fn checked_sub(mut self, count: usize) -> Option<Self>
+    where
+        T: Sized,
+    {
+        let count = -isize::try_from(count).ok()?;
+        self.ptr = crate::checked_offset::<S>(self.ptr, count, core::mem::size_of::<T>())?;
+        Some(self)
+    }
+
+    /// Calculates the distance between two pointers in elements of `T`
+    ///
+    /// Returns `None` if either pointer is null, if `T` is zero sized, or if the distance isn't a
+    /// multiple of the size of `T`.
+    pub fn checked_offset_from(self, origin: Self) -> Option<isize>
+    where
+        T: Sized,
+    {
+        crate::checked_offset_from::<S>(self.ptr, origin.ptr, core::mem::size_of::<T>())
+    }
+
+    pub fn set_ptr_value(mut self, val: ConstPtr<u8, S>) -> Self {
         self.ptr = val.ptr;
         self
     }
@@ -202,7 +316,7 @@ where
     ///
     /// # Safety
     /// This dereferences raw pointers
-    pub unsafe fn copy_to(self, dest: MutPtr<T, BASE_ADDR>, count: usize)
+    pub unsafe fn copy_to(self, dest: MutPtr<T, S>, count: usize)
     where
         T: Sized,
     {
@@ -212,7 +326,7 @@ where
     ///
     /// # Safety
     /// This dereferences raw pointers
-    pub unsafe fn copy_to_nonoverlapping(self, dest: MutPtr<T, BASE_ADDR>, count: usize)
+    pub unsafe fn copy_to_nonoverlapping(self, dest: MutPtr<T, S>, count: usize)
     where
         T: Sized,
     {
@@ -226,9 +340,9 @@ where
         self.as_wide_ptr().align_offset(align)
     }
 
-    pub fn from_raw_parts(
-        ptr: u16,
-        metadata: <<T as Pointee>::Metadata as IntoTiny>::Tiny,
+    pub const fn from_raw_parts(
+        ptr: S::Offset,
+        metadata: <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny,
     ) -> Self {
         Self {
             ptr,
@@ -238,104 +352,246 @@ where
     }
 }
 
-impl<T, const BASE_ADDR: usize> Copy for ConstPtr<T, BASE_ADDR>
+// SAFE: a `ConstPtr` is an offset and metadata, sharing it doesn't give access to the pointee.
+// Reading or writing through it, including after `cast_mut`, requires unsafe code which has to
+// rule out data races itself. Requiring `T: Sync` keeps tables of pointers in statics from
+// handing out `!Sync` data. This allows tables of tiny pointers in statics.
+unsafe impl<T, S: AddressSpace> Sync for ConstPtr<T, S>
+where
+    T: ?Sized + Sync,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+}
+
+impl<T, S: AddressSpace> Copy for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
 
-impl<T, const BASE_ADDR: usize> Clone for ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> Clone for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn clone(&self) -> Self {
         *self
     }
 }
 
-impl<T, const BASE_ADDR: usize> Eq for ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> Eq for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
-impl<T, const BASE_ADDR: usize> PartialEq for ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> PartialEq for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn eq(&self, o: &Self) -> bool {
-        self.as_wide_ptr().eq(&o.as_wide_ptr())
+        core::ptr::eq(self.as_wide_ptr(), o.as_wide_ptr())
     }
 }
-impl<T, const BASE_ADDR: usize> Ord for ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> Ord for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
+    #[allow(ambiguous_wide_pointer_comparisons)]
     fn cmp(&self, other: &Self) -> core::cmp::Ordering {
         self.as_wide_ptr().cmp(&other.as_wide_ptr())
     }
 }
-impl<T, const BASE_ADDR: usize> PartialOrd for ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> PartialOrd for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl<T, const BASE_ADDR: usize> Pointer for ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> Pointer for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.as_wide_ptr().fmt(f)
     }
 }
 
-impl<T, const BASE_ADDR: usize> core::fmt::Debug for ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> core::fmt::Debug for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         core::fmt::Debug::fmt(&self.as_wide_ptr(), f)
     }
 }
 
-impl<T, const BASE_ADDR: usize> From<MutPtr<T, BASE_ADDR>> for ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> From<MutPtr<T, S>> for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    fn from(v: MutPtr<T, BASE_ADDR>) -> Self {
+    fn from(v: MutPtr<T, S>) -> Self {
         let (ptr, metadata) = v.as_raw_parts();
         Self::from_raw_parts(ptr, metadata)
     }
 }
 
-impl<T, const BASE_ADDR: usize> From<Ref<'_, T, BASE_ADDR>> for ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> From<Ref<'_, T, S>> for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    fn from(v: Ref<'_, T, BASE_ADDR>) -> Self {
+    fn from(v: Ref<'_, T, S>) -> Self {
         v.ptr.as_ptr().into()
     }
 }
 
-impl<T, const BASE_ADDR: usize> From<RefMut<'_, T, BASE_ADDR>> for ConstPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> From<RefMut<'_, T, S>> for ConstPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    fn from(v: RefMut<'_, T, BASE_ADDR>) -> Self {
+    fn from(v: RefMut<'_, T, S>) -> Self {
         v.ptr.as_ptr().into()
     }
 }
+
+/// Creates a tiny pointer in a constant expression
+///
+/// If the address space is declared with
+/// [`address_space!(... = static ARENA)`](crate::address_space) or
+/// `address_space!(... = extern SYMBOL: Type)`, the pointer can point to any place inside of the
+/// arena:
+///
+/// ```
+/// # use tinyptr::ptr::ConstPtr;
+/// struct Node {
+///     next: ConstPtr<Node, RomSpace>,
+/// }
+///
+/// #[repr(C)]
+/// struct Rom {
+///     head: Node,
+///     a: Node,
+///     b: Node,
+/// }
+///
+/// static ROM: Rom = Rom {
+///     head: Node { next: tinyptr::static_ptr!(RomSpace, ROM.a) },
+///     // ...
+/// #   a: Node { next: tinyptr::static_ptr!(RomSpace, ROM.b) },
+/// #   b: Node { next: tinyptr::static_ptr!(RomSpace, ROM.a) },
+/// };
+/// tinyptr::address_space!(RomSpace = static ROM);
+///
+/// static TABLE: [ConstPtr<Node, RomSpace>; 2] = [
+///     tinyptr::static_ptr!(RomSpace, ROM.a),
+///     tinyptr::static_ptr!(RomSpace, ROM.b),
+/// ];
+/// ```
+///
+/// The offset is computed and checked at compile time, a place outside of the arena of the
+/// address space fails to compile. In an address space at a fixed address, such as
+/// [`Static`](crate::space::Static), the pointer is created from an address instead:
+///
+/// ```
+/// # use tinyptr::{ptr::ConstPtr, space::Static};
+/// # struct Node;
+/// type Sram = Static<0x2000_0000>;
+/// static HEAD: ConstPtr<Node, Sram> = tinyptr::static_ptr!(Sram, at 0x2000_0100);
+/// ```
+///
+/// With the default null encoding, the start of the arena can't be pointed to. `static_ptr!(mut
+/// ...)` creates a `MutPtr` and `static_ptr!(nonnull ...)` a `NonNull` instead.
+#[macro_export]
+macro_rules! static_ptr {
+    (mut $space:ty, $($place:tt)+) => {
+        $crate::static_ptr!($space, $($place)+).cast_mut()
+    };
+    (nonnull $space:ty, $($place:tt)+) => {{
+        const OFFSET: usize = $crate::static_ptr!(@offset $space, $($place)+);
+        $crate::ptr::NonNull::<_, $space>::from_non_null_offset(
+            <<$space as $crate::space::AddressSpace>::Null as $crate::space::ConstNullEncoding<
+                <$space as $crate::space::AddressSpace>::Offset,
+                { $crate::space::non_null_const::<$space>(OFFSET) },
+            >>::NON_NULL,
+            (),
+        )
+    }};
+    (@offset $space:ty, at $addr:expr) => {
+        $crate::space::encode_const::<$space>(
+            $addr - <$space as $crate::space::ConstBase>::BASE,
+        )
+    };
+    (@offset $space:ty, $place:expr) => {{
+        // SAFE: only the address of the place is taken, which is unsafe for fields of extern
+        // statics. The compiler checks that both pointers point into the same static.
+        #[allow(unused_unsafe, clippy::macro_metavars_in_unsafe)]
+        let offset = unsafe {
+            (&raw const $place)
+                .cast::<u8>()
+                .offset_from(<$space as $crate::space::StaticArena>::ARENA)
+        };
+        $crate::space::encode_const::<$space>(offset as usize)
+    }};
+    ($space:ty, $($place:tt)+) => {{
+        const OFFSET: usize = $crate::static_ptr!(@offset $space, $($place)+);
+        $crate::ptr::ConstPtr::<_, $space>::from_raw_parts(
+            <<$space as $crate::space::AddressSpace>::Offset as $crate::offset::ConstOffset<
+                OFFSET,
+            >>::OFFSET,
+            (),
+        )
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConstPtr;
+    use crate::{
+        ptr::NonNull,
+        space::{MaxNull, Static},
+    };
+
+    #[repr(C)]
+    struct Table {
+        header: u32,
+        entries: [u16; 4],
+    }
+
+    static TABLE: Table = Table {
+        header: 0xaa,
+        entries: [1, 2, 3, 4],
+    };
+    crate::address_space!(Rom: u8 = static TABLE);
+    crate::address_space!(Full: u8 = static TABLE, null MaxNull);
+
+    static ENTRY: ConstPtr<u16, Rom> = crate::static_ptr!(Rom, TABLE.entries[2]);
+    static HEADER: NonNull<u32, Full> = crate::static_ptr!(nonnull Full, TABLE.header);
+
+    #[test]
+    fn static_ptrs() {
+        assert_eq!(ENTRY.addr(), 8);
+        // SAFE: the pointer points into the static
+        assert_eq!(unsafe { *ENTRY.as_ref().unwrap() }, 3);
+        assert_eq!(HEADER.addr(), 0);
+        assert_eq!(unsafe { *HEADER.as_ref() }, 0xaa);
+    }
+
+    #[test]
+    fn fixed_address() {
+        type Sram = Static<0x2000_0000>;
+        const PTR: ConstPtr<u8, Sram> = crate::static_ptr!(Sram, at 0x2000_0100);
+        assert_eq!(PTR.addr(), 0x100);
+    }
+}
@@ -1,8 +1,25 @@
+#[cfg(any(
+    target_has_atomic = "8",
+    target_has_atomic = "16",
+    target_has_atomic = "32"
+))]
+mod atomic;
 mod const_ptr;
 mod mut_ptr;
 mod non_null;
+mod packed;
+mod rel_ptr;
+mod tagged;
+mod traits;
 mod unique;
 
+#[cfg(any(
+    target_has_atomic = "8",
+    target_has_atomic = "16",
+    target_has_atomic = "32"
+))]
+#[doc(inline)]
+pub use atomic::*;
 #[doc(inline)]
 pub use const_ptr::*;
 #[doc(inline)]
@@ -10,4 +27,12 @@ pub use mut_ptr::*;
 #[doc(inline)]
 pub use non_null::*;
 #[doc(inline)]
+pub use packed::*;
+#[doc(inline)]
+pub use rel_ptr::*;
+#[doc(inline)]
+pub use tagged::*;
+#[doc(inline)]
+pub use traits::*;
+#[doc(inline)]
 pub use unique::*;
@@ -1,29 +1,39 @@
-use core::{fmt::Pointer, marker::PhantomData, mem::MaybeUninit, ptr::Pointee};
+use core::{
+    fmt::Pointer,
+    marker::{PhantomData, Unsize},
+    mem::MaybeUninit,
+    ptr::Pointee,
+};
 
-use crate::{util::IntoTiny, Ref, RefMut};
+use crate::{
+    offset::Offset,
+    space::{AddressSpace, NullEncoding},
+    util::IntoTiny,
+    Ref, RefMut,
+};
 
 use super::{ConstPtr, NonNull};
 
-pub struct MutPtr<T, const BASE_ADDR: usize>
+pub struct MutPtr<T, S: AddressSpace>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
-    ptr: u16,
-    metadata: <<T as Pointee>::Metadata as IntoTiny>::Tiny,
-    _phantom: PhantomData<*mut T>,
+    ptr: S::Offset,
+    metadata: <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny,
+    _phantom: PhantomData<(*mut T, S)>,
 }
 
-impl<T, const BASE_ADDR: usize> MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
     pub fn new(ptr: *mut T) -> Option<Self> {
         let (ptr, metadata) = ptr.to_raw_parts();
         Some(Self {
-            ptr: crate::ptr_to_u16::<BASE_ADDR>(ptr)?,
-            metadata: metadata.into_tiny()?,
+            ptr: crate::ptr_to_offset::<S>(ptr)?,
+            metadata: IntoTiny::<S>::into_tiny(metadata)?,
             _phantom: PhantomData,
         })
     }
@@ -31,40 +41,90 @@ where
     ///
     /// # Safety
     /// The caller has to ensure that the pointer points to the initialized ram buffer. They also have to ensure that the fat pointer metadata can be represented in a compressed form
+    #[cfg_attr(feature = "validate", track_caller)]
     pub unsafe fn new_unchecked(ptr: *mut T) -> Self {
+        #[cfg(feature = "validate")]
+        crate::validate::check_ptr::<T, S>(ptr);
         let (ptr, metadata) = ptr.to_raw_parts();
         Self {
-            ptr: crate::ptr_to_u16_unchecked::<BASE_ADDR>(ptr),
-            metadata: metadata.into_tiny_unchecked(),
+            ptr: crate::ptr_to_offset_unchecked::<S>(ptr),
+            metadata: IntoTiny::<S>::into_tiny_unchecked(metadata),
             _phantom: PhantomData,
         }
     }
+    #[cfg_attr(feature = "validate", track_caller)]
     pub fn as_wide_ptr(self) -> *mut T {
+        #[cfg(feature = "validate")]
+        crate::validate::check_offset::<S>(self.ptr);
         // SAFE: this function can't be called without the ram buffer being initialized
-        let ptr = crate::u16_to_ptr::<BASE_ADDR>(self.ptr) as _;
+        let ptr = crate::offset_to_ptr::<S>(self.ptr).cast_mut();
         core::ptr::from_raw_parts_mut(
             ptr,
-            <<T as Pointee>::Metadata as IntoTiny>::from_tiny(self.metadata),
+            <<T as Pointee>::Metadata as IntoTiny<S>>::from_tiny(self.metadata),
         )
     }
     pub fn is_null(self) -> bool {
-        self.ptr == 0
+        S::Null::is_null(self.ptr)
     }
-    pub fn cast<U>(self) -> MutPtr<U, BASE_ADDR>
+    /// Casts to a pointer of another type
+    ///
+    /// The address is unchanged, so this is lossless even in address spaces with shifted offsets.
+    /// Pointer arithmetic on the result is only possible if the size of `U` is a multiple of the
+    /// granule of the address space.
+    pub fn cast<U>(self) -> MutPtr<U, S>
     where
-        <U as Pointee>::Metadata: IntoTiny,
+        <U as Pointee>::Metadata: IntoTiny<S>,
     {
         // SAFE: We know from the trait bounds and the fact that it comes from a valid pointer that this function is safe
         unsafe { MutPtr::new_unchecked(self.as_wide_ptr().cast()) }
     }
-    pub fn as_raw_parts(self) -> (u16, <<T as Pointee>::Metadata as IntoTiny>::Tiny) {
+    /// Converts to a pointer to an unsized type, for example `[T; N]` to `[T]` or `T` to
+    /// `dyn Trait`
+    ///
+    /// Returns `None` if the new metadata can't be compressed, for example if the vtable is not
+    /// part of the vtable index of the address space.
+    pub fn unsize<U>(self) -> Option<MutPtr<U, S>>
+    where
+        T: Unsize<U>,
+        U: ?Sized,
+        <U as Pointee>::Metadata: IntoTiny<S>,
+    {
+        let ptr: *mut U = self.as_wide_ptr();
+        let (_, metadata) = ptr.to_raw_parts();
+        Some(MutPtr::from_raw_parts(
+            self.ptr,
+            IntoTiny::<S>::into_tiny(metadata)?,
+        ))
+    }
+    /// Changes the mutability of the pointer
+    pub const fn cast_const(self) -> ConstPtr<T, S> {
+        ConstPtr::from_raw_parts(self.ptr, self.metadata)
+    }
+    /// Returns the offset of the pointer
+    ///
+    /// Tiny pointers don't carry provenance themselves, decoded pointers derive it from the
+    /// [root](AddressSpace::root) of the address space.
+    pub fn addr(self) -> S::Offset {
+        self.ptr
+    }
+    /// Creates a pointer with the given offset, keeping the metadata
+    pub fn with_addr(mut self, addr: S::Offset) -> Self {
+        self.ptr = addr;
+        self
+    }
+    /// Creates a pointer by mapping the offset, keeping the metadata
+    pub fn map_addr(self, f: impl FnOnce(S::Offset) -> S::Offset) -> Self {
+        let addr = f(self.ptr);
+        self.with_addr(addr)
+    }
+    pub fn as_raw_parts(self) -> (S::Offset, <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny) {
         (self.ptr, self.metadata)
     }
     /// Returns a shared reference to the pointer destination
     ///
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_ref<'a>(self) -> Option<Ref<'a, T, BASE_ADDR>> {
+    pub unsafe fn as_ref<'a>(self) -> Option<Ref<'a, T, S>> {
         if self.is_null() {
             None
         } else {
@@ -75,7 +135,7 @@ where
     ///
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_mut<'a>(self) -> Option<RefMut<'a, T, BASE_ADDR>> {
+    pub unsafe fn as_mut<'a>(self) -> Option<RefMut<'a, T, S>> {
         if self.is_null() {
             None
         } else {
@@ -86,20 +146,20 @@ where
     ///
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_ref_unchecked<'a>(self) -> Ref<'a, T, BASE_ADDR> {
+    pub unsafe fn as_ref_unchecked<'a>(self) -> Ref<'a, T, S> {
         Ref::new(NonNull::new_unchecked(self))
     }
     /// Returns a mutable reference to the pointer destination without checking for null
     ///
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_mut_unchecked<'a>(self) -> RefMut<'a, T, BASE_ADDR> {
+    pub unsafe fn as_mut_unchecked<'a>(self) -> RefMut<'a, T, S> {
         RefMut::new(NonNull::new_unchecked(self))
     }
     /// Returns a shared reference to a MaybeUninit
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_uninit_ref<'a>(self) -> Option<Ref<'a, MaybeUninit<T>, BASE_ADDR>>
+    pub unsafe fn as_uninit_ref<'a>(self) -> Option<Ref<'a, MaybeUninit<T>, S>>
     where
         T: Sized,
     {
@@ -108,7 +168,7 @@ where
     /// Returns a mutable reference to a MaybeUninit
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_uninit_mut<'a>(self) -> Option<RefMut<'a, MaybeUninit<T>, BASE_ADDR>>
+    pub unsafe fn as_uninit_mut<'a>(self) -> Option<RefMut<'a, MaybeUninit<T>, S>>
     where
         T: Sized,
     {
@@ -117,7 +177,7 @@ where
     /// Returns a shared reference to a MaybeUninit, without checking for null
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_uninit_ref_unchecked<'a>(self) -> Ref<'a, MaybeUninit<T>, BASE_ADDR>
+    pub unsafe fn as_uninit_ref_unchecked<'a>(self) -> Ref<'a, MaybeUninit<T>, S>
     where
         T: Sized,
     {
@@ -126,7 +186,7 @@ where
     /// Returns a mutable reference to a MaybeUninit, without checking for null
     /// # Safety
     /// This function is unsafe because it converts a pointer into a reference.
-    pub unsafe fn as_uninit_mut_unchecked<'a>(self) -> RefMut<'a, MaybeUninit<T>, BASE_ADDR>
+    pub unsafe fn as_uninit_mut_unchecked<'a>(self) -> RefMut<'a, MaybeUninit<T>, S>
     where
         T: Sized,
     {
@@ -136,20 +196,31 @@ where
     ///
     /// # Safety
     /// This function has undefined behaviour if count or the resulting pointer overflows
+    #[cfg_attr(feature = "validate", track_caller)]
     pub unsafe fn offset(self, count: i16) -> Self
     where
         T: Sized,
     {
+        #[cfg(feature = "validate")]
+        crate::validate::check_arithmetic::<S>(self.ptr, count as isize, core::mem::size_of::<T>());
         self.wrapping_offset(count)
     }
     /// Calculates the offset from a pointer, wrapping if count or the pointer overflows
+    ///
+    /// In address spaces with shifted offsets, the size of `T` has to be a multiple of the
+    /// granule of the address space.
     pub fn wrapping_offset(mut self, count: i16) -> Self
     where
         T: Sized,
     {
-        self.ptr = self
-            .ptr
-            .wrapping_add_signed(count.wrapping_mul(core::mem::size_of::<T>() as i16));
+        const {
+            assert!(
+                core::mem::size_of::<T>().is_multiple_of(S::GRANULE),
+                "size of T is not a multiple of the address space granule"
+            )
+        };
+        let bytes = (count as isize).wrapping_mul(core::mem::size_of::<T>() as isize);
+        self.ptr = self.ptr.wrapping_add_signed(bytes >> S::SHIFT);
         self
     }
     /// Calculates the offset between two pointers
@@ -199,7 +270,50 @@ where
         self.wrapping_offset((count as i16).wrapping_neg())
     }
 
-    pub fn set_ptr_value(mut self, val: MutPtr<u8, BASE_ADDR>) -> Self {
+    /// Calculates the offset from a pointer, checking that the result stays in the address space
+    ///
+    /// Returns `None` if the pointer is null, if `count * size_of::<T>()` overflows, or if the
+    /// result is outside of the address space or the null pointer.
+    pub fn checked_offset(mut self, count: isize) -> Option<Self>
+    where
+        T: Sized,
+    {
+        self.ptr = crate::checked_offset::<S>(self.ptr, count, core::mem::size_of::<T>())?;
+        Some(self)
+    }
+
+    /// Checked version of `add`, see [`checked_offset`](Self::checked_offset)
+    pub fn checked_add(mut self, count: usize) -> Option<Self>
+    where
+        T: Sized,
+    {
+        let count = isize::try_from(count).ok()?;
+        self.ptr = crate::checked_offset::<S>(self.ptr, count, core::mem::size_of::<T>())?;
+        Some(self)
+    }
+
+    /// Checked version of `sub`, see [`checked_offset`](Self::checked_offset)
+    pub fn checked_sub(mut self, count: usize) -> Option<Self>
+    where
+        T: Sized,
+    {
+        let count = -isize::try_from(count).ok()?;
+        self.ptr = crate::checked_offset::<S>(self.ptr, count, core::mem::size_of::<T>())?;
+        Some(self)
+    }
+
+    /// Calculates the distance between two pointers in elements of `T`
+    ///
+    /// Returns `None` if either pointer is null, if `T` is zero sized, or if the distance isn't a
+    /// multiple of the size of `T`.
+    pub fn checked_offset_from(self, origin: Self) -> Option<isize>
+    where
+        T: Sized,
+    {
+        crate::checked_offset_from::<S>(self.ptr, origin.ptr, core::mem::size_of::<T>())
+    }
+
+    pub fn set_ptr_value(mut self, val: MutPtr<u8, S>) -> Self {
         self.ptr = val.ptr;
         self
     }
@@ -269,7 +383,7 @@ where
     ///
     /// # Safety
     /// This dereferences raw pointers
-    pub unsafe fn copy_to(self, dest: MutPtr<T, BASE_ADDR>, count: usize)
+    pub unsafe fn copy_to(self, dest: MutPtr<T, S>, count: usize)
     where
         T: Sized,
     {
@@ -279,7 +393,7 @@ where
     ///
     /// # Safety
     /// This dereferences raw pointers
-    pub unsafe fn copy_to_nonoverlapping(self, dest: MutPtr<T, BASE_ADDR>, count: usize)
+    pub unsafe fn copy_to_nonoverlapping(self, dest: MutPtr<T, S>, count: usize)
     where
         T: Sized,
     {
@@ -290,7 +404,7 @@ where
     ///
     /// # Safety
     /// This dereferences raw pointers
-    pub unsafe fn copy_from(self, src: ConstPtr<T, BASE_ADDR>, count: usize)
+    pub unsafe fn copy_from(self, src: ConstPtr<T, S>, count: usize)
     where
         T: Sized,
     {
@@ -300,7 +414,7 @@ where
     ///
     /// # Safety
     /// This dereferences raw pointers
-    pub unsafe fn copy_from_nonoverlapping(self, src: ConstPtr<T, BASE_ADDR>, count: usize)
+    pub unsafe fn copy_from_nonoverlapping(self, src: ConstPtr<T, S>, count: usize)
     where
         T: Sized,
     {
@@ -350,9 +464,9 @@ where
     {
         self.as_wide_ptr().align_offset(align)
     }
-    pub fn from_raw_parts(
-        ptr: u16,
-        metadata: <<T as Pointee>::Metadata as IntoTiny>::Tiny,
+    pub const fn from_raw_parts(
+        ptr: S::Offset,
+        metadata: <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny,
     ) -> Self {
         Self {
             ptr,
@@ -362,94 +476,156 @@ where
     }
 }
 
-impl<T, const BASE_ADDR: usize> Copy for MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> Copy for MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
 
-impl<T, const BASE_ADDR: usize> Clone for MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> Clone for MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn clone(&self) -> Self {
         *self
     }
 }
 
-impl<T, const BASE_ADDR: usize> Eq for MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> Eq for MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
-impl<T, const BASE_ADDR: usize> PartialEq for MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> PartialEq for MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn eq(&self, o: &Self) -> bool {
-        self.as_wide_ptr().eq(&o.as_wide_ptr())
+        core::ptr::eq(self.as_wide_ptr(), o.as_wide_ptr())
     }
 }
-impl<T, const BASE_ADDR: usize> Ord for MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> Ord for MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
+    #[allow(ambiguous_wide_pointer_comparisons)]
     fn cmp(&self, other: &Self) -> core::cmp::Ordering {
         self.as_wide_ptr().cmp(&other.as_wide_ptr())
     }
 }
-impl<T, const BASE_ADDR: usize> PartialOrd for MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> PartialOrd for MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl<T, const BASE_ADDR: usize> Pointer for MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> Pointer for MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.as_wide_ptr().fmt(f)
     }
 }
 
-impl<T, const BASE_ADDR: usize> core::fmt::Debug for MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> core::fmt::Debug for MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         core::fmt::Debug::fmt(&self.as_wide_ptr(), f)
     }
 }
 
-impl<T, const BASE_ADDR: usize> From<ConstPtr<T, BASE_ADDR>> for MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> From<ConstPtr<T, S>> for MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    fn from(v: ConstPtr<T, BASE_ADDR>) -> Self {
+    fn from(v: ConstPtr<T, S>) -> Self {
         let (ptr, metadata) = v.as_raw_parts();
         Self::from_raw_parts(ptr, metadata)
     }
 }
 
-impl<T, const BASE_ADDR: usize> From<RefMut<'_, T, BASE_ADDR>> for MutPtr<T, BASE_ADDR>
+impl<T, S: AddressSpace> From<RefMut<'_, T, S>> for MutPtr<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    fn from(v: RefMut<'_, T, BASE_ADDR>) -> Self {
+    fn from(v: RefMut<'_, T, S>) -> Self {
         v.ptr.as_ptr()
     }
 }
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::MutPtr;
+    use crate::space::AddressSpace;
+
+    crate::host_space!(Heap: u8 = 256);
+
+    #[test]
+    fn round_trip() {
+        let raw = Heap::root().wrapping_add(8).cast::<u32>();
+        let ptr = MutPtr::<u32, Heap>::new(raw).unwrap();
+        assert_eq!(ptr.addr(), 8);
+        assert_eq!(ptr.as_wide_ptr(), raw);
+        // SAFE: the pointer is in bounds of the arena and aligned
+        unsafe {
+            ptr.write(7);
+            assert_eq!(ptr.read(), 7);
+            assert_eq!(*ptr.as_ref().unwrap(), 7);
+        }
+    }
+
+    #[test]
+    fn null() {
+        let ptr = MutPtr::<u32, Heap>::new(core::ptr::null_mut()).unwrap();
+        assert!(ptr.is_null());
+        assert!(ptr.as_wide_ptr().is_null());
+        // SAFE: the pointer is null
+        assert!(unsafe { ptr.as_ref() }.is_none());
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(MutPtr::<u8, Heap>::new(Heap::root().wrapping_add(256)).is_none());
+        assert!(MutPtr::<u8, Heap>::new(Heap::root().wrapping_sub(1)).is_none());
+        let x = 0u8;
+        assert!(MutPtr::<u8, Heap>::new((&raw const x).cast_mut()).is_none());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let ptr = MutPtr::<u16, Heap>::new(Heap::root().wrapping_add(16).cast()).unwrap();
+        let next = ptr.checked_add(4).unwrap();
+        assert_eq!(next.addr(), 24);
+        assert_eq!(next.checked_offset_from(ptr), Some(4));
+        assert_eq!(next.checked_sub(4), Some(ptr));
+        assert!(ptr.checked_add(200).is_none());
+        assert_eq!(ptr.wrapping_add(2).addr(), 20);
+        assert_eq!(ptr.with_addr(40).addr(), 40);
+    }
+
+    #[test]
+    fn slices() {
+        let raw = core::ptr::slice_from_raw_parts_mut(Heap::root().wrapping_add(32), 5);
+        let ptr = MutPtr::<[u8], Heap>::new(raw).unwrap();
+        assert_eq!(ptr.as_raw_parts(), (32, 5));
+        assert_eq!(ptr.as_wide_ptr(), raw);
+        let thin = MutPtr::<[u8; 5], Heap>::new(raw.cast()).unwrap();
+        assert_eq!(thin.unsize::<[u8]>(), Some(ptr));
+    }
+}
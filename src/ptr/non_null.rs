@@ -1,27 +1,61 @@
-use core::{marker::PhantomData, mem::MaybeUninit, num::NonZeroU16, ptr::Pointee};
+use core::{
+    marker::{PhantomData, Unsize},
+    mem::MaybeUninit,
+    ptr::Pointee,
+};
 
-use crate::{util::IntoTiny, Ref, RefMut};
+use crate::{
+    offset::Offset,
+    space::{AddressSpace, NullEncoding},
+    util::IntoTiny,
+    Ref, RefMut,
+};
 
 use super::{ConstPtr, MutPtr};
 
-pub struct NonNull<T, const BASE_ADDR: usize>
+pub struct NonNull<T, S: AddressSpace>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
-    ptr: NonZeroU16,
-    metadata: <<T as Pointee>::Metadata as IntoTiny>::Tiny,
-    _phantom: PhantomData<ConstPtr<T, BASE_ADDR>>,
+    ptr: <S::Null as NullEncoding<S::Offset>>::NonNull,
+    metadata: <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny,
+    _phantom: PhantomData<ConstPtr<T, S>>,
 }
 
-impl<T, const BASE_ADDR: usize> NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> NonNull<T, S>
 where
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
+    /// The offset of the first aligned address that isn't the null offset
+    const DANGLING: usize = {
+        let align = core::mem::align_of::<T>();
+        let step = if align > S::GRANULE {
+            align
+        } else {
+            S::GRANULE
+        } >> S::SHIFT;
+        let offset = match <S::Null as NullEncoding<S::Offset>>::NULL {
+            Some(null) if null == step => 2 * step,
+            _ => step,
+        };
+        assert!(
+            S::Offset::BITS >= usize::BITS || offset >> S::Offset::BITS == 0,
+            "no aligned offset fits into the offset type"
+        );
+        offset
+    };
+
+    /// Creates a pointer that is aligned for `T` but dangling
+    ///
+    /// This fails to compile if no aligned address other than the null pointer can be encoded
+    /// in the address space.
     pub fn dangling() -> Self {
+        // SAFE: the offset fits into the offset type and isn't the null offset
         unsafe {
-            Self::new_unchecked(MutPtr::new_unchecked(
-                (core::mem::align_of::<T>() + BASE_ADDR) as *mut T,
+            Self::new_unchecked(MutPtr::from_raw_parts(
+                S::Offset::from_usize_truncate(Self::DANGLING),
+                IntoTiny::<S>::into_tiny_unchecked(()),
             ))
         }
     }
@@ -30,7 +64,7 @@ where
     ///
     /// # Safety
     /// This function converts a pointer to a reference
-    pub unsafe fn as_uninit_ref<'a>(&self) -> Ref<'a, MaybeUninit<T>, BASE_ADDR> {
+    pub unsafe fn as_uninit_ref<'a>(&self) -> Ref<'a, MaybeUninit<T>, S> {
         self.as_ptr().as_uninit_ref_unchecked()
     }
 
@@ -38,41 +72,46 @@ where
     ///
     /// # Safety
     /// This function converts a pointer to a reference
-    pub unsafe fn as_uninit_mut<'a>(&mut self) -> RefMut<'a, MaybeUninit<T>, BASE_ADDR> {
+    pub unsafe fn as_uninit_mut<'a>(&mut self) -> RefMut<'a, MaybeUninit<T>, S> {
         self.as_ptr().as_uninit_mut_unchecked()
     }
 }
 
-impl<T, const BASE_ADDR: usize> NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> NonNull<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
     /// Creates a new NonNull<T>
     ///
     /// # Safety
     /// The caller has to ensure that ptr is not null
-    pub unsafe fn new_unchecked(ptr: MutPtr<T, BASE_ADDR>) -> Self {
+    #[cfg_attr(feature = "validate", track_caller)]
+    pub unsafe fn new_unchecked(ptr: MutPtr<T, S>) -> Self {
+        #[cfg(feature = "validate")]
+        if ptr.is_null() {
+            crate::validate::report(crate::validate::Violation::Null);
+        }
         let (ptr, metadata) = ptr.as_raw_parts();
         Self {
-            ptr: NonZeroU16::new_unchecked(ptr),
+            ptr: S::Null::to_non_null_unchecked(ptr),
             metadata,
             _phantom: PhantomData,
         }
     }
 
-    pub fn new(ptr: MutPtr<T, BASE_ADDR>) -> Option<Self> {
+    pub fn new(ptr: MutPtr<T, S>) -> Option<Self> {
         let (ptr, metadata) = ptr.as_raw_parts();
         Some(Self {
-            ptr: NonZeroU16::new(ptr)?,
+            ptr: S::Null::to_non_null(ptr)?,
             metadata,
             _phantom: PhantomData,
         })
     }
 
-    pub fn from_raw_parts(
-        data_address: NonNull<(), BASE_ADDR>,
-        metadata: <<T as Pointee>::Metadata as IntoTiny>::Tiny,
+    pub const fn from_raw_parts(
+        data_address: NonNull<(), S>,
+        metadata: <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny,
     ) -> Self {
         Self {
             ptr: data_address.ptr,
@@ -81,32 +120,114 @@ where
         }
     }
 
+    /// Creates a pointer from the non-null representation of its offset
+    ///
+    /// This is usable in constant expressions, see [`static_ptr!`](crate::static_ptr).
+    pub const fn from_non_null_offset(
+        ptr: <S::Null as NullEncoding<S::Offset>>::NonNull,
+        metadata: <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny,
+    ) -> Self {
+        Self {
+            ptr,
+            metadata,
+            _phantom: PhantomData,
+        }
+    }
+
     pub fn to_raw_parts(
         self,
     ) -> (
-        NonNull<(), BASE_ADDR>,
-        <<T as Pointee>::Metadata as IntoTiny>::Tiny,
+        NonNull<(), S>,
+        <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny,
     ) {
-        let (ptr, metadata) = self.as_ptr().as_raw_parts();
         (
             NonNull {
-                ptr: unsafe { NonZeroU16::new_unchecked(ptr) },
+                ptr: self.ptr,
                 metadata: (),
                 _phantom: PhantomData,
             },
-            metadata,
+            self.metadata,
         )
     }
 
-    pub fn as_ptr(self) -> MutPtr<T, BASE_ADDR> {
-        MutPtr::from_raw_parts(self.ptr.get(), self.metadata)
+    pub fn as_ptr(self) -> MutPtr<T, S> {
+        MutPtr::from_raw_parts(S::Null::from_non_null(self.ptr), self.metadata)
+    }
+
+    /// Converts to a pointer to an unsized type, see [`MutPtr::unsize`]
+    pub fn unsize<U>(self) -> Option<NonNull<U, S>>
+    where
+        T: Unsize<U>,
+        U: ?Sized,
+        <U as Pointee>::Metadata: IntoTiny<S>,
+    {
+        // SAFE: the offset is unchanged, so the pointer is still not null
+        Some(unsafe { NonNull::new_unchecked(self.as_ptr().unsize()?) })
+    }
+
+    /// Returns the offset of the pointer, see [`MutPtr::addr`]
+    pub fn addr(self) -> S::Offset {
+        S::Null::from_non_null(self.ptr)
+    }
+
+    /// Creates a pointer with the given offset, keeping the metadata
+    ///
+    /// Returns `None` if the offset is the null pointer.
+    pub fn with_addr(self, addr: S::Offset) -> Option<Self> {
+        Self::new(self.as_ptr().with_addr(addr))
+    }
+
+    /// Creates a pointer by mapping the offset, keeping the metadata
+    ///
+    /// Returns `None` if the new offset is the null pointer.
+    pub fn map_addr(self, f: impl FnOnce(S::Offset) -> S::Offset) -> Option<Self> {
+        Self::new(self.as_ptr().map_addr(f))
+    }
+
+    /// Calculates the offset from a pointer, checking that the result stays in the address space
+    ///
+    /// See [`MutPtr::checked_offset`]
+    pub fn checked_offset(self, count: isize) -> Option<Self>
+    where
+        T: Sized,
+    {
+        // SAFE: checked arithmetic never returns a null pointer
+        Some(unsafe { Self::new_unchecked(self.as_ptr().checked_offset(count)?) })
+    }
+
+    /// Checked version of `add`, see [`MutPtr::checked_offset`]
+    pub fn checked_add(self, count: usize) -> Option<Self>
+    where
+        T: Sized,
+    {
+        // SAFE: checked arithmetic never returns a null pointer
+        Some(unsafe { Self::new_unchecked(self.as_ptr().checked_add(count)?) })
+    }
+
+    /// Checked version of `sub`, see [`MutPtr::checked_offset`]
+    pub fn checked_sub(self, count: usize) -> Option<Self>
+    where
+        T: Sized,
+    {
+        // SAFE: checked arithmetic never returns a null pointer
+        Some(unsafe { Self::new_unchecked(self.as_ptr().checked_sub(count)?) })
+    }
+
+    /// Calculates the distance between two pointers in elements of `T`
+    ///
+    /// See [`MutPtr::checked_offset_from`]
+    pub fn checked_offset_from(self, origin: Self) -> Option<isize>
+    where
+        T: Sized,
+    {
+        self.as_ptr().checked_offset_from(origin.as_ptr())
     }
 
     /// Returns a shared reference to the value
     ///
     /// # Safety
     /// This function converts a pointer to a reference
-    pub unsafe fn as_ref<'a>(&self) -> Ref<'a, T, BASE_ADDR> {
+    pub unsafe fn as_ref<'a>(&self) -> Ref<'a, T, S> {
         self.as_ptr().as_ref_unchecked()
     }
 
@@ -114,94 +235,139 @@ where
     ///
     /// # Safety
     /// This function converts a pointer to a reference
-    pub unsafe fn as_mut<'a>(&mut self) -> RefMut<'a, T, BASE_ADDR> {
+    pub unsafe fn as_mut<'a>(&mut self) -> RefMut<'a, T, S> {
         self.as_ptr().as_mut_unchecked()
     }
 
-    pub fn cast<U>(self) -> NonNull<U, BASE_ADDR>
+    pub fn cast<U>(self) -> NonNull<U, S>
     where
-        <U as Pointee>::Metadata: IntoTiny,
+        <U as Pointee>::Metadata: IntoTiny<S>,
     {
         unsafe { NonNull::new_unchecked(self.as_ptr().cast()) }
     }
 }
 
-impl<T, const BASE_ADDR: usize> Copy for NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> Copy for NonNull<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
 
-impl<T, const BASE_ADDR: usize> Clone for NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> Clone for NonNull<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn clone(&self) -> Self {
         *self
     }
 }
 
-impl<T, const BASE_ADDR: usize> Eq for NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> Eq for NonNull<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
-impl<T, const BASE_ADDR: usize> PartialEq for NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> PartialEq for NonNull<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn eq(&self, o: &Self) -> bool {
         self.as_ptr().eq(&o.as_ptr())
     }
 }
-impl<T, const BASE_ADDR: usize> Ord for NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> Ord for NonNull<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn cmp(&self, other: &Self) -> core::cmp::Ordering {
         self.as_ptr().cmp(&other.as_ptr())
     }
 }
-impl<T, const BASE_ADDR: usize> PartialOrd for NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> PartialOrd for NonNull<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
-impl<T, const BASE_ADDR: usize> core::fmt::Pointer for NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> core::fmt::Pointer for NonNull<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.as_ptr().fmt(f)
     }
 }
 
-impl<T, const BASE_ADDR: usize> core::fmt::Debug for NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> core::fmt::Debug for NonNull<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         core::fmt::Debug::fmt(&self.as_ptr(), f)
     }
 }
 
-impl<T, const BASE_ADDR: usize> From<RefMut<'_, T, BASE_ADDR>> for NonNull<T, BASE_ADDR>
+impl<T, S: AddressSpace> From<RefMut<'_, T, S>> for NonNull<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    fn from(v: RefMut<'_, T, BASE_ADDR>) -> Self {
+    fn from(v: RefMut<'_, T, S>) -> Self {
         v.ptr
     }
 }
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::NonNull;
+    use crate::{
+        ptr::MutPtr,
+        space::{AddressSpace, MaxNull, Sentinel},
+    };
+
+    crate::host_space!(Heap: u8 = 256);
+    crate::host_space!(Full: u8 = 256, null MaxNull);
+
+    #[test]
+    fn rejects_null() {
+        assert!(NonNull::<u8, Heap>::new(MutPtr::new(core::ptr::null_mut()).unwrap()).is_none());
+        let ptr = MutPtr::<u8, Heap>::new(Heap::root().wrapping_add(1)).unwrap();
+        assert_eq!(NonNull::new(ptr).unwrap().as_ptr(), ptr);
+    }
+
+    #[test]
+    fn null_encoding() {
+        // The start of the arena is null with the default encoding, but not with `MaxNull`
+        assert!(MutPtr::<u8, Heap>::new(Heap::root()).is_none());
+        let first = MutPtr::<u8, Full>::new(Full::root()).unwrap();
+        assert_eq!(NonNull::new(first).unwrap().addr(), 0);
+        assert!(MutPtr::<u8, Full>::new(Full::root().wrapping_add(255)).is_none());
+    }
+
+    #[test]
+    fn dangling() {
+        crate::host_space!(Odd: u8 = 256, null Sentinel<1>);
+        assert_eq!(NonNull::<u8, Odd>::dangling().addr(), 2);
+        assert_eq!(NonNull::<u16, Full>::dangling().addr(), 2);
+        assert_eq!(NonNull::<u64, Heap>::dangling().addr(), 8);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let ptr = NonNull::<u8, Heap>::new(MutPtr::new(Heap::root().wrapping_add(2)).unwrap());
+        let ptr = ptr.unwrap();
+        assert_eq!(ptr.checked_add(3).unwrap().addr(), 5);
+        assert!(ptr.checked_sub(2).is_none());
+        assert!(ptr.with_addr(0).is_none());
+    }
+}
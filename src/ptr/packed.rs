@@ -0,0 +1,281 @@
+use core::marker::PhantomData;
+
+use crate::{
+    offset::Offset,
+    space::{AddressSpace, NullEncoding},
+};
+
+use super::MutPtr;
+
+/// A slice pointer that packs the offset and the length into a single `u32`
+///
+/// The lower `LEN_BITS` bits store the length of the slice, the remaining upper bits store the
+/// offset into the address space. For example with a 4 KiB arena, 12 bits are enough for the
+/// offset, leaving 20 bits for the length.
+///
+/// Packed pointers can be stored in a [`Box`](crate::boxed::Box), which then holds slices whose
+/// length doesn't fit into the length type of the address space. See [`PackedStrPtr`] for string
+/// slices.
+pub struct PackedSlicePtr<T, S: AddressSpace, const LEN_BITS: u32> {
+    packed: u32,
+    _phantom: PhantomData<(*mut [T], S)>,
+}
+
+impl<T, S: AddressSpace, const LEN_BITS: u32> PackedSlicePtr<T, S, LEN_BITS> {
+    const LEN_MASK: u32 = {
+        assert!(
+            LEN_BITS > 0 && LEN_BITS < 32,
+            "invalid number of length bits"
+        );
+        (1 << LEN_BITS) - 1
+    };
+    /// Largest offset that can be stored
+    pub const MAX_OFFSET: usize = (u32::MAX >> LEN_BITS) as usize;
+    /// Largest length that can be stored
+    pub const MAX_LEN: usize = Self::LEN_MASK as usize;
+
+    pub fn new(ptr: *mut [T]) -> Option<Self> {
+        let len = ptr.len();
+        let offset = crate::ptr_to_offset::<S>(ptr as *const ())?.to_usize();
+        if offset > Self::MAX_OFFSET || len > Self::MAX_LEN {
+            return None;
+        }
+        Some(Self::from_raw_parts(offset as u32, len as u32))
+    }
+
+    /// Creates a new packed pointer, unchecked
+    ///
+    /// # Safety
+    /// The caller has to ensure that the pointer points to the initialized ram buffer, and that the
+    /// offset and length fit into their parts of the packed representation.
+    #[cfg_attr(feature = "validate", track_caller)]
+    pub unsafe fn new_unchecked(ptr: *mut [T]) -> Self {
+        #[cfg(feature = "validate")]
+        crate::validate::check_ptr::<(), S>(ptr as *const ());
+        let len = ptr.len();
+        let offset = crate::ptr_to_offset_unchecked::<S>(ptr as *const ()).to_usize();
+        #[cfg(feature = "validate")]
+        {
+            use crate::validate::{report, Violation};
+            if offset > Self::MAX_OFFSET {
+                report(Violation::OutOfRange { addr: ptr.addr() });
+            }
+            if len > Self::MAX_LEN {
+                report(Violation::Metadata);
+            }
+        }
+        Self::from_raw_parts(offset as u32, len as u32)
+    }
+
+    #[cfg_attr(feature = "validate", track_caller)]
+    pub fn as_wide_ptr(self) -> *mut [T] {
+        #[cfg(feature = "validate")]
+        crate::validate::check_offset::<S>(self.offset());
+        let ptr = crate::offset_to_ptr::<S>(self.offset())
+            .cast_mut()
+            .cast::<T>();
+        core::ptr::slice_from_raw_parts_mut(ptr, self.len())
+    }
+
+    /// Converts to a regular slice pointer, if the length fits into it
+    pub fn as_ptr(self) -> Option<MutPtr<[T], S>> {
+        MutPtr::new(self.as_wide_ptr())
+    }
+
+    pub(crate) fn offset(self) -> S::Offset {
+        S::Offset::from_usize_truncate((self.packed >> LEN_BITS) as usize)
+    }
+
+    pub fn len(self) -> usize {
+        (self.packed & Self::LEN_MASK) as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_null(self) -> bool {
+        S::Null::is_null(self.offset())
+    }
+
+    /// Returns the offset and the length
+    pub fn as_raw_parts(self) -> (u32, u32) {
+        (self.packed >> LEN_BITS, self.packed & Self::LEN_MASK)
+    }
+
+    /// Creates a packed pointer from an offset and a length, truncating both
+    pub fn from_raw_parts(offset: u32, len: u32) -> Self {
+        Self {
+            packed: (offset << LEN_BITS) | (len & Self::LEN_MASK),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, S: AddressSpace, const LEN_BITS: u32> Copy for PackedSlicePtr<T, S, LEN_BITS> {}
+
+impl<T, S: AddressSpace, const LEN_BITS: u32> Clone for PackedSlicePtr<T, S, LEN_BITS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, S: AddressSpace, const LEN_BITS: u32> Eq for PackedSlicePtr<T, S, LEN_BITS> {}
+impl<T, S: AddressSpace, const LEN_BITS: u32> PartialEq for PackedSlicePtr<T, S, LEN_BITS> {
+    fn eq(&self, o: &Self) -> bool {
+        self.packed == o.packed
+    }
+}
+
+impl<T, S: AddressSpace, const LEN_BITS: u32> core::fmt::Pointer
+    for PackedSlicePtr<T, S, LEN_BITS>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Pointer::fmt(&self.as_wide_ptr(), f)
+    }
+}
+
+impl<T, S: AddressSpace, const LEN_BITS: u32> core::fmt::Debug for PackedSlicePtr<T, S, LEN_BITS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&self.as_wide_ptr(), f)
+    }
+}
+
+/// A `str` pointer that packs the offset and the length into a single `u32`
+///
+/// This is a [`PackedSlicePtr`] to the bytes of the string.
+pub struct PackedStrPtr<S: AddressSpace, const LEN_BITS: u32>(PackedSlicePtr<u8, S, LEN_BITS>);
+
+impl<S: AddressSpace, const LEN_BITS: u32> PackedStrPtr<S, LEN_BITS> {
+    pub fn new(ptr: *mut str) -> Option<Self> {
+        PackedSlicePtr::new(ptr as *mut [u8]).map(Self)
+    }
+
+    /// Creates a new packed pointer, unchecked
+    ///
+    /// # Safety
+    /// See [`PackedSlicePtr::new_unchecked`]
+    #[cfg_attr(feature = "validate", track_caller)]
+    pub unsafe fn new_unchecked(ptr: *mut str) -> Self {
+        Self(PackedSlicePtr::new_unchecked(ptr as *mut [u8]))
+    }
+
+    #[cfg_attr(feature = "validate", track_caller)]
+    pub fn as_wide_ptr(self) -> *mut str {
+        self.0.as_wide_ptr() as *mut str
+    }
+
+    /// Converts to a regular `str` pointer, if the length fits into it
+    pub fn as_ptr(self) -> Option<MutPtr<str, S>> {
+        MutPtr::new(self.as_wide_ptr())
+    }
+
+    /// Returns the packed pointer to the bytes of the string
+    pub fn as_bytes(self) -> PackedSlicePtr<u8, S, LEN_BITS> {
+        self.0
+    }
+
+    pub fn len(self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn is_null(self) -> bool {
+        self.0.is_null()
+    }
+}
+
+impl<S: AddressSpace, const LEN_BITS: u32> Copy for PackedStrPtr<S, LEN_BITS> {}
+
+impl<S: AddressSpace, const LEN_BITS: u32> Clone for PackedStrPtr<S, LEN_BITS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: AddressSpace, const LEN_BITS: u32> Eq for PackedStrPtr<S, LEN_BITS> {}
+impl<S: AddressSpace, const LEN_BITS: u32> PartialEq for PackedStrPtr<S, LEN_BITS> {
+    fn eq(&self, o: &Self) -> bool {
+        self.0 == o.0
+    }
+}
+
+impl<S: AddressSpace, const LEN_BITS: u32> core::fmt::Pointer for PackedStrPtr<S, LEN_BITS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Pointer::fmt(&self.as_wide_ptr(), f)
+    }
+}
+
+impl<S: AddressSpace, const LEN_BITS: u32> core::fmt::Debug for PackedStrPtr<S, LEN_BITS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&self.as_wide_ptr(), f)
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::{PackedSlicePtr, PackedStrPtr};
+    use crate::{boxed::Box, ptr::TinyPointer, space::AddressSpace};
+
+    crate::host_space!(Heap: u16 = 4096);
+
+    #[test]
+    fn slices() {
+        let raw = core::ptr::slice_from_raw_parts_mut(Heap::root().wrapping_add(300), 70_000);
+        let ptr = PackedSlicePtr::<u8, Heap, 20>::new(raw).unwrap();
+        assert_eq!(ptr.as_raw_parts(), (300, 70_000));
+        assert_eq!(ptr.as_wide_ptr(), raw);
+        // The length doesn't fit into the `u16` length of the address space
+        assert!(ptr.as_ptr().is_none());
+        assert!(PackedSlicePtr::<u8, Heap, 24>::new(raw).is_none());
+    }
+
+    #[test]
+    fn strs() {
+        let raw = core::ptr::slice_from_raw_parts_mut(Heap::root().wrapping_add(8), 3);
+        let ptr = PackedStrPtr::<Heap, 8>::new(raw as *mut str).unwrap();
+        assert_eq!(ptr.len(), 3);
+        assert_eq!(ptr.as_wide_ptr() as *mut [u8], raw);
+        assert_eq!(ptr.as_ptr().unwrap().as_raw_parts(), (8, 3));
+    }
+
+    #[test]
+    fn null() {
+        let ptr = PackedSlicePtr::<u8, Heap, 8>::new(core::ptr::slice_from_raw_parts_mut(
+            core::ptr::null_mut(),
+            0,
+        ))
+        .unwrap();
+        assert!(ptr.is_null() && ptr.is_empty());
+    }
+
+    #[test]
+    fn boxed() {
+        crate::host_space!(Words: u8 >> 2 = 1024);
+        let b: Box<[u8; 300], _, Words> = Box::new_in([7; 300], Words::arena());
+        let (raw, alloc) = Box::into_raw_with_allocator(b);
+        let ptr = PackedSlicePtr::<u8, Words, 10>::new(raw.as_wide_ptr() as *mut [u8]).unwrap();
+        // SAFE: the pointer and the allocator come from a box
+        let mut b = unsafe { Box::from_pointer_in(ptr, alloc) };
+        b[299] = 8;
+        assert_eq!((b.len(), b[0], b[299]), (300, 7, 8));
+        let (ptr, alloc) = Box::into_pointer_with_allocator(b);
+        assert_eq!(ptr.addr(), raw.addr());
+        // SAFE: the pointer and the allocator come from a box
+        drop(unsafe { Box::from_pointer_in(ptr, alloc) });
+        assert_eq!(Words::arena().live(), 0);
+    }
+
+    #[test]
+    fn boxed_str() {
+        let b: Box<[u8; 5], _, Heap> = Box::new_in(*b"tiny!", Heap::arena());
+        let (raw, alloc) = Box::into_raw_with_allocator(b);
+        let ptr = PackedStrPtr::<Heap, 8>::new(raw.as_wide_ptr() as *mut [u8] as *mut str).unwrap();
+        // SAFE: the pointer and the allocator come from a box, and the bytes are valid UTF-8
+        let b = unsafe { Box::from_pointer_in(ptr, alloc) };
+        assert_eq!(&*b, "tiny!");
+    }
+}
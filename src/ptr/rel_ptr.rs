@@ -0,0 +1,248 @@
+use core::{marker::PhantomData, ptr::Pointee};
+
+use crate::{space::AddressSpace, util::IntoTiny};
+
+use super::MutPtr;
+
+/// A self-relative tiny pointer
+///
+/// Instead of an offset into the address space, a `RelPtr` stores the distance in bytes from its
+/// own address to the target. Data structures that only point into themselves can therefore be
+/// copied, placed in flash, or mapped at any address without fixing up their pointers.
+///
+/// Moving a `RelPtr` on its own changes its target, which is why it is neither `Copy` nor
+/// `Clone` and all accessors take `&self`. A distance of zero is used as the null pointer.
+///
+/// The target derives its provenance from the `RelPtr` itself, so it has to be part of the same
+/// allocation, wherever that is. The address space `S` is only used for compressing the pointer
+/// metadata and by [`as_ptr`](RelPtr::as_ptr), so the target doesn't have to be inside of it.
+/// Accessing the target through a reference to the `RelPtr` goes beyond the memory that the
+/// reference covers, which Stacked Borrows rejects, so Miri has to be run with Tree Borrows.
+#[repr(C)]
+pub struct RelPtr<T, S: AddressSpace>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    offset: i16,
+    metadata: <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny,
+    _phantom: PhantomData<(*mut T, S)>,
+}
+
+impl<T, S: AddressSpace> RelPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    fn addr(&self) -> usize {
+        (self as *const Self).addr()
+    }
+
+    /// Points the pointer at `ptr`
+    ///
+    /// Returns `None` and leaves the pointer unchanged if the target is too far away, or if the
+    /// metadata can't be compressed.
+    pub fn set(&mut self, ptr: *mut T) -> Option<()> {
+        let (ptr, metadata) = ptr.to_raw_parts();
+        let metadata = IntoTiny::<S>::into_tiny(metadata)?;
+        let offset = if ptr.is_null() {
+            0
+        } else {
+            match ptr.addr().wrapping_sub(self.addr()) as isize {
+                0 => return None,
+                offset => offset.try_into().ok()?,
+            }
+        };
+        self.offset = offset;
+        self.metadata = metadata;
+        Some(())
+    }
+
+    /// Points the pointer at `ptr` without checking the distance
+    ///
+    /// # Safety
+    /// The caller has to ensure that the target is less than 32 KiB away from the pointer, and
+    /// that the fat pointer metadata can be represented in a compressed form
+    #[cfg_attr(feature = "validate", track_caller)]
+    pub unsafe fn set_unchecked(&mut self, ptr: *mut T) {
+        let (ptr, metadata) = ptr.to_raw_parts();
+        #[cfg(feature = "validate")]
+        {
+            use crate::validate::{report, Violation};
+            let distance = ptr.addr().wrapping_sub(self.addr()) as isize;
+            if !ptr.is_null() && (distance == 0 || i16::try_from(distance).is_err()) {
+                report(Violation::OutOfRange { addr: ptr.addr() });
+            }
+            if IntoTiny::<S>::into_tiny(metadata).is_none() {
+                report(Violation::Metadata);
+            }
+        }
+        self.offset = if ptr.is_null() {
+            0
+        } else {
+            ptr.addr().wrapping_sub(self.addr()) as i16
+        };
+        self.metadata = IntoTiny::<S>::into_tiny_unchecked(metadata);
+    }
+
+    /// Sets the pointer to null
+    pub fn set_null(&mut self)
+    where
+        <T as Pointee>::Metadata: Default,
+    {
+        self.offset = 0;
+        self.metadata = <<T as Pointee>::Metadata as IntoTiny<S>>::into_tiny(Default::default())
+            .expect("Default metadata can't be compressed");
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.offset == 0
+    }
+
+    pub fn as_wide_ptr(&self) -> *mut T {
+        self.target((self as *const Self).cast_mut())
+    }
+
+    /// Returns the target, with the provenance of `this`
+    fn target(&self, this: *mut Self) -> *mut T {
+        let ptr = if self.is_null() {
+            core::ptr::null_mut()
+        } else {
+            this.wrapping_byte_offset(self.offset as isize).cast::<()>()
+        };
+        core::ptr::from_raw_parts_mut(
+            ptr,
+            <<T as Pointee>::Metadata as IntoTiny<S>>::from_tiny(self.metadata),
+        )
+    }
+
+    /// Converts the pointer to an absolute tiny pointer
+    pub fn as_ptr(&self) -> Option<MutPtr<T, S>> {
+        MutPtr::new(self.as_wide_ptr())
+    }
+
+    /// Returns the distance from the pointer to its target, and the compressed metadata
+    pub fn as_raw_parts(&self) -> (i16, <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny) {
+        (self.offset, self.metadata)
+    }
+
+    /// Returns a shared reference to the pointer destination
+    ///
+    /// # Safety
+    /// This function is unsafe because it converts a pointer into a reference.
+    pub unsafe fn as_ref(&self) -> Option<&T> {
+        self.as_wide_ptr().as_ref()
+    }
+
+    /// Returns a mutable reference to the pointer destination
+    ///
+    /// # Safety
+    /// This function is unsafe because it converts a pointer into a reference.
+    pub unsafe fn as_mut(&mut self) -> Option<&mut T> {
+        let this: *mut Self = self;
+        (*this).target(this).as_mut()
+    }
+}
+
+impl<T, S: AddressSpace> RelPtr<T, S>
+where
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    /// Creates a new null pointer
+    pub fn null() -> Self {
+        Self {
+            offset: 0,
+            // SAFE: () is always representable
+            metadata: unsafe { IntoTiny::<S>::into_tiny_unchecked(()) },
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, S: AddressSpace> Default for RelPtr<T, S>
+where
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T, S: AddressSpace> core::fmt::Pointer for RelPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Pointer::fmt(&self.as_wide_ptr(), f)
+    }
+}
+
+impl<T, S: AddressSpace> core::fmt::Debug for RelPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&self.as_wide_ptr(), f)
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::RelPtr;
+    use crate::boxed::Box;
+
+    crate::host_space!(Heap: u16 = 256);
+
+    #[repr(C)]
+    struct Node {
+        next: RelPtr<[u8; 4], Heap>,
+        data: [u8; 4],
+    }
+
+    // Stacked Borrows doesn't allow self-relative targets
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn points_into_self() {
+        let mut node: Box<Node, _, Heap> = Box::new_in(
+            Node {
+                next: RelPtr::null(),
+                data: [1, 2, 3, 4],
+            },
+            Heap::arena(),
+        );
+        assert!(node.next.is_null());
+        let data = &raw mut node.data;
+        node.next.set(data).unwrap();
+        assert_eq!(node.next.as_raw_parts(), (2, ()));
+        // SAFE: the target is a field of the same allocation
+        assert_eq!(unsafe { node.next.as_ref() }, Some(&[1, 2, 3, 4]));
+        node.next.set_null();
+        assert!(node.next.as_ptr().unwrap().is_null());
+    }
+
+    // Stacked Borrows doesn't allow self-relative targets
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn outside_of_the_address_space() {
+        let mut node = Node {
+            next: RelPtr::null(),
+            data: [5, 6, 7, 8],
+        };
+        let data = &raw mut node.data;
+        node.next.set(data).unwrap();
+        assert!(node.next.as_ptr().is_none());
+        // SAFE: the target is a field of the same struct
+        unsafe { node.next.as_mut() }.unwrap()[0] = 9;
+        assert_eq!(node.data, [9, 6, 7, 8]);
+    }
+
+    #[test]
+    fn rejects_self() {
+        let mut ptr = RelPtr::<u8, Heap>::null();
+        let raw = (&raw mut ptr).cast::<u8>();
+        assert!(ptr.set(raw).is_none());
+        assert!(ptr.is_null());
+    }
+}
@@ -0,0 +1,213 @@
+use core::marker::PhantomData;
+
+use crate::{
+    offset::Offset,
+    space::{AddressSpace, NullEncoding},
+};
+
+use super::{MutPtr, NonNull};
+
+/// A tiny pointer with `BITS` tag bits stored in the low bits of its offset
+///
+/// The tag bits have to be zero in the offset of every pointer that is stored, which is the case
+/// if the pointee is aligned to at least `GRANULE << BITS` bytes relative to the base of the
+/// address space.
+///
+/// If the null offset has tag bits set, for example with [`MaxNull`](crate::space::MaxNull), the
+/// null pointer is stored as the null offset with its tag bits cleared. The address with that
+/// offset can't be tagged.
+pub struct TaggedPtr<T, S: AddressSpace, const BITS: u32> {
+    ptr: S::Offset,
+    _phantom: PhantomData<(*mut T, S)>,
+}
+
+impl<T, S: AddressSpace, const BITS: u32> TaggedPtr<T, S, BITS> {
+    /// Mask of the tag bits in the offset
+    pub const TAG_MASK: usize = {
+        assert!(BITS < S::Offset::BITS, "too many tag bits");
+        (1 << BITS) - 1
+    };
+
+    /// The untagged offset that the null pointer is stored as
+    fn null_offset() -> Option<usize> {
+        <S::Null as NullEncoding<S::Offset>>::NULL.map(|null| null & !Self::TAG_MASK)
+    }
+
+    /// Returns the untagged offset of the pointer, or `None` if it can't be tagged
+    fn untagged(ptr: MutPtr<T, S>) -> Option<usize> {
+        if ptr.is_null() {
+            return Self::null_offset();
+        }
+        let (ptr, ()) = ptr.as_raw_parts();
+        let ptr = ptr.to_usize();
+        if ptr & Self::TAG_MASK != 0 || Some(ptr) == Self::null_offset() {
+            return None;
+        }
+        Some(ptr)
+    }
+
+    /// Creates a new tagged pointer
+    ///
+    /// Returns `None` if the tag bits of the pointer are not zero, or if the tag doesn't fit.
+    pub fn new(ptr: MutPtr<T, S>, tag: usize) -> Option<Self> {
+        let ptr = Self::untagged(ptr)?;
+        if tag & !Self::TAG_MASK != 0 {
+            return None;
+        }
+        Some(Self {
+            ptr: S::Offset::from_usize_truncate(ptr | tag),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Creates a new tagged pointer without checking the alignment or the tag
+    ///
+    /// # Safety
+    /// The caller has to ensure that the tag bits of the pointer are zero and that the tag fits in
+    /// `BITS` bits.
+    #[cfg_attr(feature = "validate", track_caller)]
+    pub unsafe fn new_unchecked(ptr: MutPtr<T, S>, tag: usize) -> Self {
+        #[cfg(feature = "validate")]
+        {
+            use crate::validate::{report, Violation};
+            if Self::untagged(ptr).is_none() {
+                report(Violation::Misaligned {
+                    addr: S::decode(ptr.addr()),
+                    align: S::GRANULE << BITS,
+                });
+            }
+            if tag & !Self::TAG_MASK != 0 {
+                report(Violation::Tag { tag });
+            }
+        }
+        let ptr = if ptr.is_null() {
+            Self::null_offset().unwrap_unchecked()
+        } else {
+            ptr.addr().to_usize() & !Self::TAG_MASK
+        };
+        Self {
+            ptr: S::Offset::from_usize_truncate(ptr | tag),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the pointer without the tag
+    pub fn ptr(self) -> MutPtr<T, S> {
+        let ptr = self.ptr.to_usize() & !Self::TAG_MASK;
+        if Some(ptr) == Self::null_offset() {
+            // SAFE: the null offset is only stored if the address space has one
+            return MutPtr::from_raw_parts(unsafe { S::Null::null().unwrap_unchecked() }, ());
+        }
+        MutPtr::from_raw_parts(S::Offset::from_usize_truncate(ptr), ())
+    }
+
+    /// Returns the pointer without the tag, if it is not null
+    pub fn as_non_null(self) -> Option<NonNull<T, S>> {
+        NonNull::new(self.ptr())
+    }
+
+    pub fn tag(self) -> usize {
+        self.ptr.to_usize() & Self::TAG_MASK
+    }
+
+    /// Returns a copy of the pointer with a different tag
+    ///
+    /// # Panics
+    /// This function panics if the tag doesn't fit in `BITS` bits.
+    pub fn with_tag(self, tag: usize) -> Self {
+        assert!(tag & !Self::TAG_MASK == 0, "tag out of range");
+        Self {
+            ptr: S::Offset::from_usize_truncate((self.ptr.to_usize() & !Self::TAG_MASK) | tag),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns a copy of the pointer with a different pointer value, keeping the tag
+    ///
+    /// Returns `None` if the tag bits of the new pointer are not zero.
+    pub fn with_ptr(self, ptr: MutPtr<T, S>) -> Option<Self> {
+        Self::new(ptr, self.tag())
+    }
+
+    pub fn is_null(self) -> bool {
+        self.ptr().is_null()
+    }
+
+    /// Returns the raw offset, including the tag
+    pub fn as_raw(self) -> S::Offset {
+        self.ptr
+    }
+
+    /// Creates a tagged pointer from a raw offset, including the tag
+    pub fn from_raw(ptr: S::Offset) -> Self {
+        Self {
+            ptr,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, S: AddressSpace, const BITS: u32> Copy for TaggedPtr<T, S, BITS> {}
+
+impl<T, S: AddressSpace, const BITS: u32> Clone for TaggedPtr<T, S, BITS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, S: AddressSpace, const BITS: u32> Eq for TaggedPtr<T, S, BITS> {}
+impl<T, S: AddressSpace, const BITS: u32> PartialEq for TaggedPtr<T, S, BITS> {
+    fn eq(&self, o: &Self) -> bool {
+        self.ptr == o.ptr
+    }
+}
+
+impl<T, S: AddressSpace, const BITS: u32> core::fmt::Debug for TaggedPtr<T, S, BITS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TaggedPtr")
+            .field("ptr", &self.ptr())
+            .field("tag", &self.tag())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::TaggedPtr;
+    use crate::{
+        ptr::MutPtr,
+        space::{AddressSpace, MaxNull},
+    };
+
+    crate::host_space!(Heap: u8 = 256);
+    crate::host_space!(Full: u8 = 256, null MaxNull);
+
+    #[test]
+    fn tags() {
+        let ptr = MutPtr::<u32, Heap>::new(Heap::root().wrapping_add(8).cast()).unwrap();
+        let tagged = TaggedPtr::<u32, Heap, 2>::new(ptr, 3).unwrap();
+        assert_eq!((tagged.ptr(), tagged.tag()), (ptr, 3));
+        let tagged = tagged.with_tag(1);
+        assert_eq!((tagged.ptr(), tagged.tag()), (ptr, 1));
+        assert_eq!(TaggedPtr::from_raw(tagged.as_raw()), tagged);
+        assert!(TaggedPtr::<u32, Heap, 2>::new(ptr, 4).is_none());
+    }
+
+    #[test]
+    fn unaligned() {
+        let ptr = MutPtr::<u32, Heap>::new(Heap::root().wrapping_add(6).cast()).unwrap();
+        assert!(TaggedPtr::<u32, Heap, 2>::new(ptr, 0).is_none());
+    }
+
+    #[test]
+    fn null() {
+        let null = MutPtr::<u32, Full>::new(core::ptr::null_mut()).unwrap();
+        let tagged = TaggedPtr::<u32, Full, 2>::new(null, 2).unwrap();
+        assert!(tagged.is_null());
+        assert_eq!(tagged.tag(), 2);
+        assert!(tagged.as_non_null().is_none());
+        // The null offset with its tag bits cleared is reserved for null
+        let last = MutPtr::<u32, Full>::new(Full::root().wrapping_add(252).cast()).unwrap();
+        assert!(TaggedPtr::<u32, Full, 2>::new(last, 0).is_none());
+    }
+}
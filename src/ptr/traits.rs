@@ -0,0 +1,322 @@
+use core::ptr::Pointee;
+
+use crate::{space::AddressSpace, util::IntoTiny, Ref};
+
+use super::{ConstPtr, MutPtr, NonNull, PackedSlicePtr, PackedStrPtr, Unique};
+
+/// Compressed metadata of a pointer to `T` in the address space `S`
+pub type TinyMetadata<T, S> = <<T as Pointee>::Metadata as IntoTiny<S>>::Tiny;
+
+/// Operations shared by all tiny pointer types
+///
+/// This allows writing code that is generic over the kind of pointer, for example a list node
+/// that is parameterised over its link type. See [`RawPointer`], [`NonNullPointer`] and
+/// [`OwningPointer`] for the more specific kinds of pointers.
+pub trait TinyPointer: Copy {
+    /// The type that is pointed to
+    type Target: ?Sized + Pointee<Metadata: IntoTiny<Self::Space, Tiny = Self::Metadata>>;
+    /// The address space the pointer points into
+    type Space: AddressSpace;
+    /// The compressed pointer metadata
+    type Metadata: Copy;
+    /// The same kind of pointer with a different target type
+    type Cast<U>: TinyPointer<Target = U, Space = Self::Space>;
+
+    /// Converts the pointer to a raw pointer
+    fn as_mut_ptr(self) -> MutPtr<Self::Target, Self::Space>;
+
+    /// Casts to a pointer of another type
+    fn cast<U>(self) -> Self::Cast<U>;
+
+    fn as_raw_parts(self) -> (<Self::Space as AddressSpace>::Offset, Self::Metadata) {
+        self.as_mut_ptr().as_raw_parts()
+    }
+
+    fn as_wide_ptr(self) -> *mut Self::Target {
+        self.as_mut_ptr().as_wide_ptr()
+    }
+
+    fn addr(self) -> <Self::Space as AddressSpace>::Offset {
+        self.as_mut_ptr().addr()
+    }
+
+    fn is_null(self) -> bool {
+        self.as_mut_ptr().is_null()
+    }
+
+    /// Returns a shared reference to the pointee, or `None` if the pointer is null
+    ///
+    /// # Safety
+    /// This function is unsafe because it converts a pointer into a reference.
+    unsafe fn as_ref<'a>(self) -> Option<Ref<'a, Self::Target, Self::Space>> {
+        self.as_mut_ptr().as_ref()
+    }
+}
+
+/// A tiny pointer that can be null and can be created from any raw pointer
+pub trait RawPointer: TinyPointer {
+    fn from_mut_ptr(ptr: MutPtr<Self::Target, Self::Space>) -> Self;
+
+    /// See [`MutPtr::wrapping_offset`]
+    fn wrapping_offset(self, count: i16) -> Self
+    where
+        Self::Target: Sized,
+    {
+        Self::from_mut_ptr(self.as_mut_ptr().wrapping_offset(count))
+    }
+
+    /// See [`MutPtr::checked_offset`]
+    fn checked_offset(self, count: isize) -> Option<Self>
+    where
+        Self::Target: Sized,
+    {
+        Some(Self::from_mut_ptr(self.as_mut_ptr().checked_offset(count)?))
+    }
+}
+
+/// A tiny pointer that is never null
+pub trait NonNullPointer: TinyPointer {
+    fn as_non_null(self) -> NonNull<Self::Target, Self::Space>;
+
+    /// Creates the pointer from a non-null pointer
+    ///
+    /// # Safety
+    /// The caller has to uphold the invariants of the pointer type, for example that an
+    /// [`OwningPointer`] is the only pointer to its pointee.
+    unsafe fn from_non_null(ptr: NonNull<Self::Target, Self::Space>) -> Self;
+
+    /// See [`NonNull::checked_offset`]
+    ///
+    /// # Safety
+    /// See [`from_non_null`](NonNullPointer::from_non_null)
+    unsafe fn checked_offset(self, count: isize) -> Option<Self>
+    where
+        Self::Target: Sized,
+    {
+        Some(Self::from_non_null(
+            self.as_non_null().checked_offset(count)?,
+        ))
+    }
+}
+
+/// A non-null tiny pointer that owns its pointee
+///
+/// Owning containers like [`Box`](crate::boxed::Box) are generic over this trait.
+pub trait OwningPointer: NonNullPointer {}
+
+impl<T, S: AddressSpace> TinyPointer for MutPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    type Target = T;
+    type Space = S;
+    type Metadata = TinyMetadata<T, S>;
+    type Cast<U> = MutPtr<U, S>;
+
+    fn as_mut_ptr(self) -> MutPtr<T, S> {
+        self
+    }
+
+    fn cast<U>(self) -> MutPtr<U, S> {
+        MutPtr::cast(self)
+    }
+}
+
+impl<T, S: AddressSpace> RawPointer for MutPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    fn from_mut_ptr(ptr: MutPtr<T, S>) -> Self {
+        ptr
+    }
+}
+
+impl<T, S: AddressSpace> TinyPointer for ConstPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    type Target = T;
+    type Space = S;
+    type Metadata = TinyMetadata<T, S>;
+    type Cast<U> = ConstPtr<U, S>;
+
+    fn as_mut_ptr(self) -> MutPtr<T, S> {
+        self.cast_mut()
+    }
+
+    fn cast<U>(self) -> ConstPtr<U, S> {
+        ConstPtr::cast(self)
+    }
+}
+
+impl<T, S: AddressSpace> RawPointer for ConstPtr<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    fn from_mut_ptr(ptr: MutPtr<T, S>) -> Self {
+        ptr.cast_const()
+    }
+}
+
+impl<T, S: AddressSpace> TinyPointer for NonNull<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    type Target = T;
+    type Space = S;
+    type Metadata = TinyMetadata<T, S>;
+    type Cast<U> = NonNull<U, S>;
+
+    fn as_mut_ptr(self) -> MutPtr<T, S> {
+        self.as_ptr()
+    }
+
+    fn cast<U>(self) -> NonNull<U, S> {
+        NonNull::cast(self)
+    }
+}
+
+impl<T, S: AddressSpace> NonNullPointer for NonNull<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    fn as_non_null(self) -> NonNull<T, S> {
+        self
+    }
+
+    unsafe fn from_non_null(ptr: NonNull<T, S>) -> Self {
+        ptr
+    }
+}
+
+impl<T, S: AddressSpace> TinyPointer for Unique<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    type Target = T;
+    type Space = S;
+    type Metadata = TinyMetadata<T, S>;
+    type Cast<U> = Unique<U, S>;
+
+    fn as_mut_ptr(self) -> MutPtr<T, S> {
+        self.as_ptr()
+    }
+
+    fn cast<U>(self) -> Unique<U, S> {
+        Unique::cast(self)
+    }
+}
+
+impl<T, S: AddressSpace> NonNullPointer for Unique<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    fn as_non_null(self) -> NonNull<T, S> {
+        self.as_non_null_ptr()
+    }
+
+    unsafe fn from_non_null(ptr: NonNull<T, S>) -> Self {
+        Unique::from_nonnull(ptr)
+    }
+}
+
+impl<T, S: AddressSpace> OwningPointer for Unique<T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+}
+
+/// The length of a packed pointer doesn't have to fit into the length type of the address space,
+/// so converting it to a [`MutPtr`] panics if it doesn't. The other methods work on any packed
+/// pointer.
+impl<T, S: AddressSpace, const LEN_BITS: u32> TinyPointer for PackedSlicePtr<T, S, LEN_BITS> {
+    type Target = [T];
+    type Space = S;
+    type Metadata = TinyMetadata<[T], S>;
+    type Cast<U> = MutPtr<U, S>;
+
+    fn as_mut_ptr(self) -> MutPtr<[T], S> {
+        self.as_ptr()
+            .expect("length doesn't fit into the address space")
+    }
+
+    fn cast<U>(self) -> MutPtr<U, S> {
+        MutPtr::from_raw_parts(self.offset(), ())
+    }
+
+    fn as_wide_ptr(self) -> *mut [T] {
+        PackedSlicePtr::as_wide_ptr(self)
+    }
+
+    fn addr(self) -> S::Offset {
+        self.offset()
+    }
+
+    fn is_null(self) -> bool {
+        PackedSlicePtr::is_null(self)
+    }
+}
+
+impl<T, S: AddressSpace, const LEN_BITS: u32> NonNullPointer for PackedSlicePtr<T, S, LEN_BITS> {
+    fn as_non_null(self) -> NonNull<[T], S> {
+        NonNull::new(self.as_mut_ptr()).expect("packed pointer is null")
+    }
+
+    unsafe fn from_non_null(ptr: NonNull<[T], S>) -> Self {
+        PackedSlicePtr::new_unchecked(ptr.as_ptr().as_wide_ptr())
+    }
+}
+
+/// A packed pointer owns its pointee when it is stored in a [`Box`](crate::boxed::Box)
+impl<T, S: AddressSpace, const LEN_BITS: u32> OwningPointer for PackedSlicePtr<T, S, LEN_BITS> {}
+
+/// See the implementation for [`PackedSlicePtr`]
+impl<S: AddressSpace, const LEN_BITS: u32> TinyPointer for PackedStrPtr<S, LEN_BITS> {
+    type Target = str;
+    type Space = S;
+    type Metadata = TinyMetadata<str, S>;
+    type Cast<U> = MutPtr<U, S>;
+
+    fn as_mut_ptr(self) -> MutPtr<str, S> {
+        self.as_ptr()
+            .expect("length doesn't fit into the address space")
+    }
+
+    fn cast<U>(self) -> MutPtr<U, S> {
+        self.as_bytes().cast()
+    }
+
+    fn as_wide_ptr(self) -> *mut str {
+        PackedStrPtr::as_wide_ptr(self)
+    }
+
+    fn addr(self) -> S::Offset {
+        self.as_bytes().offset()
+    }
+
+    fn is_null(self) -> bool {
+        PackedStrPtr::is_null(self)
+    }
+}
+
+impl<S: AddressSpace, const LEN_BITS: u32> NonNullPointer for PackedStrPtr<S, LEN_BITS> {
+    fn as_non_null(self) -> NonNull<str, S> {
+        NonNull::new(self.as_mut_ptr()).expect("packed pointer is null")
+    }
+
+    unsafe fn from_non_null(ptr: NonNull<str, S>) -> Self {
+        PackedStrPtr::new_unchecked(ptr.as_ptr().as_wide_ptr())
+    }
+}
+
+/// A packed pointer owns its pointee when it is stored in a [`Box`](crate::boxed::Box)
+impl<S: AddressSpace, const LEN_BITS: u32> OwningPointer for PackedStrPtr<S, LEN_BITS> {}
@@ -1,6 +1,9 @@
-use core::{marker::PhantomData, ptr::Pointee};
+use core::{
+    marker::{PhantomData, Unsize},
+    ptr::Pointee,
+};
 
-use crate::{util::IntoTiny, Ref, RefMut};
+use crate::{space::AddressSpace, util::IntoTiny, Ref, RefMut};
 
 use super::{MutPtr, NonNull};
 
@@ -25,12 +28,12 @@ use super::{MutPtr, NonNull};
 /// Unlike `*mut T`, `Unique<T>` is covariant over `T`. This should always be correct
 /// for any type which upholds Unique's aliasing requirements.
 #[repr(transparent)]
-pub struct Unique<T, const BASE_ADDR: usize>
+pub struct Unique<T, S: AddressSpace>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
-    ptr: NonNull<T, BASE_ADDR>,
+    ptr: NonNull<T, S>,
     _phantom: PhantomData<T>,
 }
 
@@ -38,10 +41,10 @@ where
 /// reference is unaliased. Note that this aliasing invariant is
 /// unenforced by the type system; the abstraction using the
 /// `Unique` must enforce it.
-unsafe impl<T, const BASE_ADDR: usize> Send for Unique<T, BASE_ADDR>
+unsafe impl<T, S: AddressSpace> Send for Unique<T, S>
 where
     T: Send + ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
 }
 
@@ -49,16 +52,16 @@ where
 /// reference is unaliased. Note that this aliasing invariant is
 /// unenforced by the type system; the abstraction using the
 /// `Unique` must enforce it.
-unsafe impl<T, const BASE_ADDR: usize> Sync for Unique<T, BASE_ADDR>
+unsafe impl<T, S: AddressSpace> Sync for Unique<T, S>
 where
     T: Sync + ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
 }
 
-impl<T, const BASE_ADDR: usize> Unique<T, BASE_ADDR>
+impl<T, S: AddressSpace> Unique<T, S>
 where
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
     /// Creates a new `Unique` that is dangling, but well-aligned.
     ///
@@ -78,12 +81,12 @@ where
     }
 }
 
-impl<T, const BASE_ADDR: usize> Unique<T, BASE_ADDR>
+impl<T, S: AddressSpace> Unique<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
-    pub const fn from_nonnull(nn: NonNull<T, BASE_ADDR>) -> Self {
+    pub const fn from_nonnull(nn: NonNull<T, S>) -> Self {
         Self {
             ptr: nn,
             _phantom: PhantomData,
@@ -94,20 +97,27 @@ where
     /// # Safety
     ///
     /// `ptr` must be non-null.
-    pub unsafe fn new_unchecked(ptr: MutPtr<T, BASE_ADDR>) -> Self {
+    #[cfg_attr(feature = "validate", track_caller)]
+    pub unsafe fn new_unchecked(ptr: MutPtr<T, S>) -> Self {
         Self::from_nonnull(NonNull::new_unchecked(ptr))
     }
     /// Creates a new `Unique` if `ptr` is non-null.
     #[inline]
-    pub fn new(ptr: MutPtr<T, BASE_ADDR>) -> Option<Self> {
+    pub fn new(ptr: MutPtr<T, S>) -> Option<Self> {
         Some(Self::from_nonnull(NonNull::new(ptr)?))
     }
     /// Acquires the underlying `*mut` pointer.
     #[inline]
-    pub fn as_ptr(self) -> MutPtr<T, BASE_ADDR> {
+    pub fn as_ptr(self) -> MutPtr<T, S> {
         self.ptr.as_ptr()
     }
 
+    /// Acquires the underlying `NonNull` pointer.
+    #[inline]
+    pub const fn as_non_null_ptr(self) -> NonNull<T, S> {
+        self.ptr
+    }
+
     /// Dereferences the content.
     ///
     /// The resulting lifetime is bound to self so this behaves "as if"
@@ -116,7 +126,7 @@ where
     /// # Safety
     /// the caller must guarantee that `self` meets all the requirements for a reference.
     #[inline]
-    pub unsafe fn as_ref(&self) -> Ref<'_, T, BASE_ADDR> {
+    pub unsafe fn as_ref(&self) -> Ref<'_, T, S> {
         self.ptr.as_ref()
     }
 
@@ -129,72 +139,83 @@ where
     /// # Safety
     /// the caller must guarantee that `self` meets all the requirements for a mutable reference.
     #[inline]
-    pub unsafe fn as_mut(&mut self) -> RefMut<'_, T, BASE_ADDR> {
+    pub unsafe fn as_mut(&mut self) -> RefMut<'_, T, S> {
         self.ptr.as_mut()
     }
 
+    /// Converts to a pointer to an unsized type, see [`MutPtr::unsize`]
+    #[inline]
+    pub fn unsize<U>(self) -> Option<Unique<U, S>>
+    where
+        T: Unsize<U>,
+        U: ?Sized,
+        <U as Pointee>::Metadata: IntoTiny<S>,
+    {
+        Some(Unique::from_nonnull(self.ptr.unsize()?))
+    }
+
     /// Casts to a pointer of another type.
     #[inline]
-    pub fn cast<U>(self) -> Unique<U, BASE_ADDR>
+    pub fn cast<U>(self) -> Unique<U, S>
     where
-        <U as Pointee>::Metadata: IntoTiny,
+        <U as Pointee>::Metadata: IntoTiny<S>,
     {
         Unique::from_nonnull(self.ptr.cast())
     }
 }
 
-impl<T, const BASE_ADDR: usize> Clone for Unique<T, BASE_ADDR>
+impl<T, S: AddressSpace> Clone for Unique<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
     fn clone(&self) -> Self {
         *self
     }
 }
-impl<T, const BASE_ADDR: usize> Copy for Unique<T, BASE_ADDR>
+impl<T, S: AddressSpace> Copy for Unique<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny,
+    <T as Pointee>::Metadata: IntoTiny<S>,
 {
 }
 
-impl<T, const BASE_ADDR: usize> core::fmt::Pointer for Unique<T, BASE_ADDR>
+impl<T, S: AddressSpace> core::fmt::Pointer for Unique<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.ptr.fmt(f)
     }
 }
 
-impl<T, const BASE_ADDR: usize> core::fmt::Debug for Unique<T, BASE_ADDR>
+impl<T, S: AddressSpace> core::fmt::Debug for Unique<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         core::fmt::Debug::fmt(&self.ptr, f)
     }
 }
 
-impl<T, const BASE_ADDR: usize> From<NonNull<T, BASE_ADDR>> for Unique<T, BASE_ADDR>
+impl<T, S: AddressSpace> From<NonNull<T, S>> for Unique<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    fn from(nn: NonNull<T, BASE_ADDR>) -> Self {
+    fn from(nn: NonNull<T, S>) -> Self {
         Self::from_nonnull(nn)
     }
 }
 
-impl<T, const BASE_ADDR: usize> From<RefMut<'_, T, BASE_ADDR>> for Unique<T, BASE_ADDR>
+impl<T, S: AddressSpace> From<RefMut<'_, T, S>> for Unique<T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    fn from(v: RefMut<'_, T, BASE_ADDR>) -> Self {
+    fn from(v: RefMut<'_, T, S>) -> Self {
         v.ptr.into()
     }
 }
@@ -1,22 +1,22 @@
 use core::{borrow::Borrow, marker::PhantomData, ops::Deref, ptr::Pointee};
 
-use crate::{ptr::NonNull, util::IntoTiny};
+use crate::{ptr::NonNull, space::AddressSpace, util::IntoTiny, RefMut};
 
-pub struct Ref<'a, T, const BASE_ADDR: usize>
+pub struct Ref<'a, T, S: AddressSpace>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    pub(crate) ptr: NonNull<T, BASE_ADDR>,
+    pub(crate) ptr: NonNull<T, S>,
     _phantom: PhantomData<&'a T>,
 }
 
-impl<'a, T, const BASE_ADDR: usize> Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Ref<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    pub(crate) const unsafe fn new(ptr: NonNull<T, BASE_ADDR>) -> Self {
+    pub(crate) const unsafe fn new(ptr: NonNull<T, S>) -> Self {
         Self {
             ptr,
             _phantom: PhantomData,
@@ -28,27 +28,27 @@ where
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> Copy for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Copy for Ref<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
 
-impl<'a, T, const BASE_ADDR: usize> Clone for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Clone for Ref<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn clone(&self) -> Self {
         *self
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> Deref for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Deref for Ref<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     type Target = T;
 
@@ -57,174 +57,185 @@ where
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> Borrow<T> for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Borrow<T> for Ref<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn borrow(&self) -> &T {
-        &*self
+        self
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Pointer for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Pointer for Ref<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.ptr.fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Binary for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Binary for Ref<'a, T, S>
 where
     T: ?Sized + core::fmt::Binary,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Debug for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Debug for Ref<'a, T, S>
 where
     T: ?Sized + core::fmt::Debug,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Display for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Display for Ref<'a, T, S>
 where
     T: ?Sized + core::fmt::Display,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::LowerExp for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::LowerExp for Ref<'a, T, S>
 where
     T: ?Sized + core::fmt::LowerExp,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::LowerHex for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::LowerHex for Ref<'a, T, S>
 where
     T: ?Sized + core::fmt::LowerHex,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Octal for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Octal for Ref<'a, T, S>
 where
     T: ?Sized + core::fmt::Octal,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::UpperExp for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::UpperExp for Ref<'a, T, S>
 where
     T: ?Sized + core::fmt::UpperExp,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::UpperHex for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::UpperHex for Ref<'a, T, S>
 where
     T: ?Sized + core::fmt::UpperHex,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> PartialOrd for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> PartialOrd for Ref<'a, T, S>
 where
     T: ?Sized + PartialOrd,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
-        (**self).partial_cmp(&*other)
+        (**self).partial_cmp(&**other)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> Ord for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Ord for Ref<'a, T, S>
 where
     T: ?Sized + Ord,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn cmp(&self, other: &Self) -> core::cmp::Ordering {
-        (**self).cmp(&*other)
+        (**self).cmp(&**other)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> PartialEq for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> PartialEq for Ref<'a, T, S>
 where
     T: ?Sized + PartialEq,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn eq(&self, other: &Self) -> bool {
-        (**self).eq(&*other)
+        (**self).eq(&**other)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> Eq for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Eq for Ref<'a, T, S>
 where
     T: ?Sized + Eq,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
 
-impl<'a, T, U, const BASE_ADDR: usize> AsRef<U> for Ref<'a, T, BASE_ADDR>
+impl<'a, T, U, S: AddressSpace> AsRef<U> for Ref<'a, T, S>
 where
     T: ?Sized + AsRef<U>,
     U: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn as_ref(&self) -> &U {
         (**self).as_ref()
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::hash::Hash for Ref<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::hash::Hash for Ref<'a, T, S>
 where
     T: ?Sized + core::hash::Hash,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
         (**self).hash(state)
     }
 }
 
-unsafe impl<'a, T, const BASE_ADDR: usize> Send for Ref<'a, T, BASE_ADDR>
+unsafe impl<'a, T, S: AddressSpace> Send for Ref<'a, T, S>
 where
     T: ?Sized + Sync,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
 
-unsafe impl<'a, T, const BASE_ADDR: usize> Sync for Ref<'a, T, BASE_ADDR>
+unsafe impl<'a, T, S: AddressSpace> Sync for Ref<'a, T, S>
 where
     T: ?Sized + Sync,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
+
+impl<'a, T, S: AddressSpace> From<RefMut<'a, T, S>> for Ref<'a, T, S>
+where
+    T: ?Sized,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
+{
+    fn from(v: RefMut<'a, T, S>) -> Self {
+        // SAFE: the mutable reference is given up
+        unsafe { Ref::new(v.ptr) }
+    }
+}
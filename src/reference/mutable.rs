@@ -5,23 +5,23 @@ use core::{
     ptr::Pointee,
 };
 
-use crate::{ptr::NonNull, util::IntoTiny};
+use crate::{ptr::NonNull, space::AddressSpace, util::IntoTiny};
 
-pub struct RefMut<'a, T, const BASE_ADDR: usize>
+pub struct RefMut<'a, T, S: AddressSpace>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    pub(crate) ptr: NonNull<T, BASE_ADDR>,
+    pub(crate) ptr: NonNull<T, S>,
     _phantom: PhantomData<&'a T>,
 }
 
-impl<'a, T, const BASE_ADDR: usize> RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> RefMut<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
-    pub(crate) const unsafe fn new(ptr: NonNull<T, BASE_ADDR>) -> Self {
+    pub(crate) const unsafe fn new(ptr: NonNull<T, S>) -> Self {
         Self {
             ptr,
             _phantom: PhantomData,
@@ -37,10 +37,10 @@ where
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> Deref for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Deref for RefMut<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     type Target = T;
 
@@ -49,199 +49,199 @@ where
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> Borrow<T> for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Borrow<T> for RefMut<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn borrow(&self) -> &T {
-        &*self
+        self
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> DerefMut for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> DerefMut for RefMut<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe { &mut *self.ptr.as_ptr().as_wide_ptr() }
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> BorrowMut<T> for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> BorrowMut<T> for RefMut<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn borrow_mut(&mut self) -> &mut T {
         &mut *self
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Pointer for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Pointer for RefMut<'a, T, S>
 where
     T: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.ptr.fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Binary for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Binary for RefMut<'a, T, S>
 where
     T: ?Sized + core::fmt::Binary,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Debug for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Debug for RefMut<'a, T, S>
 where
     T: ?Sized + core::fmt::Debug,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Display for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Display for RefMut<'a, T, S>
 where
     T: ?Sized + core::fmt::Display,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::LowerExp for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::LowerExp for RefMut<'a, T, S>
 where
     T: ?Sized + core::fmt::LowerExp,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::LowerHex for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::LowerHex for RefMut<'a, T, S>
 where
     T: ?Sized + core::fmt::LowerHex,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Octal for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Octal for RefMut<'a, T, S>
 where
     T: ?Sized + core::fmt::Octal,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::UpperExp for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::UpperExp for RefMut<'a, T, S>
 where
     T: ?Sized + core::fmt::UpperExp,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::UpperHex for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::UpperHex for RefMut<'a, T, S>
 where
     T: ?Sized + core::fmt::UpperHex,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         (**self).fmt(f)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> PartialOrd for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> PartialOrd for RefMut<'a, T, S>
 where
     T: ?Sized + PartialOrd,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
-        (**self).partial_cmp(&*other)
+        (**self).partial_cmp(&**other)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> Ord for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Ord for RefMut<'a, T, S>
 where
     T: ?Sized + Ord,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn cmp(&self, other: &Self) -> core::cmp::Ordering {
-        (**self).cmp(&*other)
+        (**self).cmp(&**other)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> PartialEq for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> PartialEq for RefMut<'a, T, S>
 where
     T: ?Sized + PartialEq,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn eq(&self, other: &Self) -> bool {
-        (**self).eq(&*other)
+        (**self).eq(&**other)
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> Eq for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> Eq for RefMut<'a, T, S>
 where
     T: ?Sized + Eq,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
 
-impl<'a, T, U, const BASE_ADDR: usize> AsRef<U> for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, U, S: AddressSpace> AsRef<U> for RefMut<'a, T, S>
 where
     T: ?Sized + AsRef<U>,
     U: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn as_ref(&self) -> &U {
         (**self).as_ref()
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::hash::Hash for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::hash::Hash for RefMut<'a, T, S>
 where
     T: ?Sized + core::hash::Hash,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
         (**self).hash(state)
     }
 }
 
-impl<'a, T, U, const BASE_ADDR: usize> AsMut<U> for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, U, S: AddressSpace> AsMut<U> for RefMut<'a, T, S>
 where
     T: ?Sized + AsMut<U>,
     U: ?Sized,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn as_mut(&mut self) -> &mut U {
         (**self).as_mut()
     }
 }
 
-impl<'a, T, const BASE_ADDR: usize> core::fmt::Write for RefMut<'a, T, BASE_ADDR>
+impl<'a, T, S: AddressSpace> core::fmt::Write for RefMut<'a, T, S>
 where
     T: ?Sized + core::fmt::Write,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         (**self).write_str(s)
@@ -256,16 +256,16 @@ where
     }
 }
 
-unsafe impl<'a, T, const BASE_ADDR: usize> Send for RefMut<'a, T, BASE_ADDR>
+unsafe impl<'a, T, S: AddressSpace> Send for RefMut<'a, T, S>
 where
     T: ?Sized + Send,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
 
-unsafe impl<'a, T, const BASE_ADDR: usize> Sync for RefMut<'a, T, BASE_ADDR>
+unsafe impl<'a, T, S: AddressSpace> Sync for RefMut<'a, T, S>
 where
     T: ?Sized + Sync,
-    <T as Pointee>::Metadata: IntoTiny + Copy,
+    <T as Pointee>::Metadata: IntoTiny<S> + Copy,
 {
 }
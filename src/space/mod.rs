@@ -0,0 +1,492 @@
+//! Address spaces describe where the tiny pointer arena lives in memory.
+//!
+//! Every tiny pointer type is generic over an [`AddressSpace`], which is used to translate between
+//! the compressed offset and a full-width pointer.
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::{offset::Offset, util::FullVTables};
+
+mod null;
+mod region;
+
+#[doc(inline)]
+pub use null::*;
+#[doc(inline)]
+pub use region::*;
+
+/// A region of memory that tiny pointers are relative to
+pub trait AddressSpace {
+    /// The integer type that offsets into the arena are stored as
+    ///
+    /// This also limits the size of the arena, for example to 64 KiB for `u16`.
+    type Offset: Offset;
+
+    /// Number of bits the stored offsets are shifted right by
+    ///
+    /// Every address in the arena that can be pointed to has to be aligned to `1 << SHIFT` bytes,
+    /// in exchange a `u16` offset can address `64 KiB << SHIFT` of memory.
+    const SHIFT: u32 = 0;
+
+    /// The alignment of every address that can be represented in the address space
+    const GRANULE: usize = 1 << Self::SHIFT;
+
+    /// How trait object vtables are compressed, see [`VTableEncoding`](crate::util::VTableEncoding)
+    type VTables = FullVTables;
+
+    /// The integer type that slice and `str` lengths are stored as
+    ///
+    /// See [`WithLen`] for changing the length type of an existing address space.
+    type Len: Offset = Self::Offset;
+
+    /// Which offset is used for the null pointer, see [`NullEncoding`]
+    type Null: NullEncoding<Self::Offset> = Sentinel<0>;
+
+    /// Returns the start address of the arena
+    fn base() -> usize;
+
+    /// Returns the pointer that decoded pointers derive their provenance from
+    ///
+    /// The default implementation uses the exposed provenance of the base address, which is
+    /// appropriate for arenas at fixed addresses or linker symbols.
+    #[inline(always)]
+    fn root() -> *mut u8 {
+        core::ptr::with_exposed_provenance_mut(Self::base())
+    }
+
+    /// Converts an address to an offset, returning `None` if it is outside of the address space.
+    ///
+    /// The null pointer is handled by the caller, it is never passed to this function. Addresses
+    /// that are encoded to the null offset are rejected by the caller as well.
+    #[inline(always)]
+    fn encode(addr: usize) -> Option<Self::Offset> {
+        let offset = addr.checked_sub(Self::base())?;
+        if offset & (Self::GRANULE - 1) != 0 {
+            return None;
+        }
+        Self::Offset::from_usize(offset >> Self::SHIFT)
+    }
+
+    /// Converts an address to an offset without any range checking.
+    ///
+    /// # Safety
+    /// The caller has to ensure that the address is inside of the address space.
+    #[inline(always)]
+    unsafe fn encode_unchecked(addr: usize) -> Self::Offset {
+        Self::Offset::from_usize_truncate((addr - Self::base()) >> Self::SHIFT)
+    }
+
+    /// Converts an offset back to an address
+    ///
+    /// The address wraps around if the address space extends past the end of the address range,
+    /// see [`try_decode`](AddressSpace::try_decode).
+    #[inline(always)]
+    fn decode(offset: Self::Offset) -> usize {
+        Self::base().wrapping_add(offset.to_usize() << Self::SHIFT)
+    }
+
+    /// Converts an offset back to an address, returning `None` if it doesn't point into the
+    /// address space
+    ///
+    /// Every offset points into a contiguous address space, so the default implementation only
+    /// returns `None` if the address overflows.
+    #[inline(always)]
+    fn try_decode(offset: Self::Offset) -> Option<usize> {
+        Self::base().checked_add(offset.to_usize() << Self::SHIFT)
+    }
+
+    /// Converts an offset back to a pointer
+    ///
+    /// The default implementation derives the provenance from the [root](AddressSpace::root).
+    #[inline(always)]
+    fn decode_ptr(offset: Self::Offset) -> *mut u8 {
+        Self::root().with_addr(Self::decode(offset))
+    }
+}
+
+/// An address space whose arena is a static, so that the offsets of pointers into it are known
+/// at compile time
+///
+/// This is implemented by address spaces declared with
+/// [`address_space!(... = static ARENA)`](crate::address_space) or
+/// `address_space!(... = extern SYMBOL: Type)`, and used by [`static_ptr!`](crate::static_ptr).
+pub trait StaticArena: AddressSpace {
+    /// Points to the start of the arena
+    const ARENA: *const u8;
+}
+
+/// An address space whose base address is known at compile time
+///
+/// This is used by [`static_ptr!`](crate::static_ptr) to create pointers to fixed addresses.
+pub trait ConstBase: AddressSpace {
+    const BASE: usize;
+}
+
+/// Converts a byte offset from the base of the address space to a stored offset at compile time
+///
+/// This is used by [`static_ptr!`](crate::static_ptr).
+///
+/// # Panics
+/// This function panics if the offset isn't a multiple of the granule, or if it is the null
+/// offset. In a constant expression, this is a compile error.
+pub const fn encode_const<S: AddressSpace>(offset: usize) -> usize {
+    assert!(
+        offset.is_multiple_of(S::GRANULE),
+        "offset not aligned to the granule"
+    );
+    let offset = offset >> S::SHIFT;
+    if let Some(null) = <S::Null as NullEncoding<S::Offset>>::NULL {
+        assert!(offset != null, "offset is the null pointer");
+    }
+    offset
+}
+
+/// Converts a stored offset to the integer that its non-null representation is made from
+///
+/// This is the `V` parameter of [`ConstNullEncoding`].
+pub const fn non_null_const<S: AddressSpace>(offset: usize) -> usize {
+    match <S::Null as NullEncoding<S::Offset>>::NULL {
+        Some(null) => offset ^ null,
+        None => offset,
+    }
+}
+
+/// An address space whose base address is known at compile time
+pub struct Static<const BASE_ADDR: usize, O = u16, const SHIFT: u32 = 0>(PhantomData<O>);
+
+impl<const BASE_ADDR: usize, O: Offset, const SHIFT: u32> AddressSpace
+    for Static<BASE_ADDR, O, SHIFT>
+{
+    type Offset = O;
+    const SHIFT: u32 = SHIFT;
+
+    #[inline(always)]
+    fn base() -> usize {
+        BASE_ADDR
+    }
+}
+
+impl<const BASE_ADDR: usize, O: Offset, const SHIFT: u32> ConstBase
+    for Static<BASE_ADDR, O, SHIFT>
+{
+    const BASE: usize = BASE_ADDR;
+}
+
+/// Wraps an address space, storing slice lengths as `L` instead of the default
+///
+/// For example, `MutPtr<[u8], WithLen<Sram, u8>>` can only point to slices of up to 255
+/// elements, but is 3 bytes large if `Sram` uses [`Unaligned<u16>`](crate::offset::Unaligned)
+/// offsets. Pointers can be converted between the two address spaces through their wide
+/// pointers.
+pub struct WithLen<S, L>(PhantomData<(S, L)>);
+
+impl<S: AddressSpace, L: Offset> AddressSpace for WithLen<S, L> {
+    type Offset = S::Offset;
+    const SHIFT: u32 = S::SHIFT;
+    type VTables = S::VTables;
+    type Len = L;
+    type Null = S::Null;
+
+    #[inline(always)]
+    fn base() -> usize {
+        S::base()
+    }
+
+    #[inline(always)]
+    fn root() -> *mut u8 {
+        S::root()
+    }
+
+    #[inline(always)]
+    fn encode(addr: usize) -> Option<Self::Offset> {
+        S::encode(addr)
+    }
+
+    #[inline(always)]
+    unsafe fn encode_unchecked(addr: usize) -> Self::Offset {
+        S::encode_unchecked(addr)
+    }
+
+    #[inline(always)]
+    fn decode(offset: Self::Offset) -> usize {
+        S::decode(offset)
+    }
+
+    #[inline(always)]
+    fn try_decode(offset: Self::Offset) -> Option<usize> {
+        S::try_decode(offset)
+    }
+
+    #[inline(always)]
+    fn decode_ptr(offset: Self::Offset) -> *mut u8 {
+        S::decode_ptr(offset)
+    }
+}
+
+/// Storage for an arena root pointer that is only known at runtime
+///
+/// This is normally declared through the [`address_space!`](crate::address_space) macro.
+pub struct RuntimeBase(AtomicPtr<u8>);
+
+const UNINIT: usize = usize::MAX;
+
+impl RuntimeBase {
+    /// Creates storage for a root pointer that hasn't been set yet
+    pub const fn new() -> Self {
+        Self(AtomicPtr::new(core::ptr::without_provenance_mut(UNINIT)))
+    }
+
+    /// Sets the root pointer of the arena.
+    ///
+    /// # Panics
+    /// This function panics if the root pointer has already been set.
+    pub fn init(&self, root: *mut u8) {
+        assert!(self.try_init(root), "address space initialized twice");
+    }
+
+    /// Sets the root pointer of the arena, returning false if it has already been set.
+    ///
+    /// On targets without compare-and-swap, concurrent calls are only excluded if the
+    /// `critical-section` feature is enabled.
+    pub fn try_init(&self, root: *mut u8) -> bool {
+        if root.addr() == UNINIT {
+            return false;
+        }
+        #[cfg(target_has_atomic = "ptr")]
+        return self
+            .0
+            .compare_exchange(
+                core::ptr::without_provenance_mut(UNINIT),
+                root,
+                Ordering::Release,
+                Ordering::Relaxed,
+            )
+            .is_ok();
+        #[cfg(all(not(target_has_atomic = "ptr"), feature = "critical-section"))]
+        return critical_section::with(|_| self.store_if_uninit(root));
+        #[cfg(all(not(target_has_atomic = "ptr"), not(feature = "critical-section")))]
+        return self.store_if_uninit(root);
+    }
+
+    #[cfg(not(target_has_atomic = "ptr"))]
+    fn store_if_uninit(&self, root: *mut u8) -> bool {
+        if self.is_initialized() {
+            return false;
+        }
+        self.0.store(root, Ordering::Release);
+        true
+    }
+
+    /// Returns whether the root pointer has been set
+    pub fn is_initialized(&self) -> bool {
+        self.0.load(Ordering::Relaxed).addr() != UNINIT
+    }
+
+    /// Returns the root pointer.
+    ///
+    /// Using a tiny pointer in an address space that hasn't been initialized yet results in
+    /// garbage pointers, but it is checked in debug builds.
+    #[inline(always)]
+    pub fn get(&self) -> *mut u8 {
+        let root = self.0.load(Ordering::Acquire);
+        debug_assert!(
+            root.addr() != UNINIT,
+            "address space used before initialization"
+        );
+        root
+    }
+}
+
+impl Default for RuntimeBase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Declares a new address space type
+///
+/// The base address can come from a linker symbol:
+///
+/// ```
+/// tinyptr::address_space!(pub Sram = extern _sram_start);
+/// ```
+///
+/// If the symbol is given a type, it is declared as an extern static of that type, and
+/// [`static_ptr!`](crate::static_ptr) can create pointers to its fields in constant expressions:
+///
+/// ```
+/// # #[repr(C)]
+/// # struct SramLayout {
+/// #     head: u32,
+/// # }
+/// tinyptr::address_space!(pub Sram = extern _sram_start: SramLayout);
+/// ```
+///
+/// or be set once at startup, for example to a heap buffer on a host build:
+///
+/// ```
+/// # let mut buffer = vec![0u8; 256];
+/// tinyptr::address_space!(pub Heap = runtime);
+///
+/// Heap::init(buffer.as_mut_ptr());
+/// ```
+///
+/// The offset type defaults to `u16`, but can be specified after the name:
+///
+/// ```
+/// tinyptr::address_space!(pub Sram: u32 = extern _sram_start);
+/// ```
+///
+/// Offsets can also be shifted, in which case only aligned addresses can be pointed to:
+///
+/// ```
+/// // 256 KiB of 4 byte aligned objects
+/// tinyptr::address_space!(pub Sram: u16 >> 2 = extern _sram_start);
+/// ```
+///
+/// Trait object vtables can be compressed with a table declared by
+/// [`vtable_index!`](crate::vtable_index):
+///
+/// ```
+/// # tinyptr::vtable_index! { pub VTables {} }
+/// tinyptr::address_space!(pub Sram = extern _sram_start, vtables VTables);
+/// ```
+///
+/// The arena can also be a static, which allows creating pointers into it in constant
+/// expressions with [`static_ptr!`](crate::static_ptr). Pointers derive their provenance from
+/// the static, so parts of the arena that are written to have to be wrapped in an `UnsafeCell`:
+///
+/// ```
+/// # static ROM: [u8; 16] = [0; 16];
+/// tinyptr::address_space!(pub Rom = static ROM);
+/// ```
+///
+/// and the null pointer encoding can be changed, for example to make the first byte of the arena
+/// addressable:
+///
+/// ```
+/// tinyptr::address_space!(pub Sram = extern _sram_start, null tinyptr::space::MaxNull);
+/// ```
+#[macro_export]
+macro_rules! address_space {
+    ($(#[$meta:meta])* $vis:vis $name:ident: $offset:ty >> $shift:literal = extern $symbol:ident: $layout:ty $(, vtables $vtables:ty)? $(, null $null:ty)?) => {
+        extern "C" {
+            $vis static $symbol: $layout;
+        }
+
+        $(#[$meta])*
+        $vis struct $name;
+
+        impl $crate::space::AddressSpace for $name {
+            type Offset = $offset;
+            const SHIFT: u32 = $shift;
+            $(type VTables = $vtables;)?
+            $(type Null = $null;)?
+
+            #[inline(always)]
+            fn base() -> usize {
+                (&raw const $symbol).addr()
+            }
+        }
+
+        impl $crate::space::StaticArena for $name {
+            const ARENA: *const u8 = (&raw const $symbol).cast();
+        }
+    };
+    ($(#[$meta:meta])* $vis:vis $name:ident: $offset:ty >> $shift:literal = extern $symbol:ident $(, vtables $vtables:ty)? $(, null $null:ty)?) => {
+        $(#[$meta])*
+        $vis struct $name;
+
+        impl $crate::space::AddressSpace for $name {
+            type Offset = $offset;
+            const SHIFT: u32 = $shift;
+            $(type VTables = $vtables;)?
+            $(type Null = $null;)?
+
+            #[inline(always)]
+            fn base() -> usize {
+                extern "C" {
+                    static $symbol: u8;
+                }
+                // SAFE: only the address of the symbol is taken
+                unsafe { core::ptr::addr_of!($symbol).addr() }
+            }
+        }
+    };
+    ($(#[$meta:meta])* $vis:vis $name:ident: $offset:ty >> $shift:literal = static $arena:path $(, vtables $vtables:ty)? $(, null $null:ty)?) => {
+        $(#[$meta])*
+        $vis struct $name;
+
+        impl $crate::space::AddressSpace for $name {
+            type Offset = $offset;
+            const SHIFT: u32 = $shift;
+            $(type VTables = $vtables;)?
+            $(type Null = $null;)?
+
+            #[inline(always)]
+            fn base() -> usize {
+                core::ptr::addr_of!($arena).addr()
+            }
+
+            #[inline(always)]
+            fn root() -> *mut u8 {
+                core::ptr::addr_of!($arena).cast_mut().cast()
+            }
+        }
+
+        impl $crate::space::StaticArena for $name {
+            const ARENA: *const u8 = core::ptr::addr_of!($arena).cast();
+        }
+    };
+    ($(#[$meta:meta])* $vis:vis $name:ident: $offset:ty >> $shift:literal = runtime $(, vtables $vtables:ty)? $(, null $null:ty)?) => {
+        $(#[$meta])*
+        $vis struct $name;
+
+        impl $name {
+            fn storage() -> &'static $crate::space::RuntimeBase {
+                static BASE: $crate::space::RuntimeBase = $crate::space::RuntimeBase::new();
+                &BASE
+            }
+
+            /// Sets the root pointer of the address space.
+            ///
+            /// # Panics
+            /// This function panics if the root pointer has already been set.
+            #[allow(dead_code)]
+            $vis fn init(root: *mut u8) {
+                Self::storage().init(root)
+            }
+
+            /// Sets the root pointer, returning false if it has already been set.
+            #[allow(dead_code)]
+            $vis fn try_init(root: *mut u8) -> bool {
+                Self::storage().try_init(root)
+            }
+        }
+
+        impl $crate::space::AddressSpace for $name {
+            type Offset = $offset;
+            const SHIFT: u32 = $shift;
+            $(type VTables = $vtables;)?
+            $(type Null = $null;)?
+
+            #[inline(always)]
+            fn base() -> usize {
+                Self::storage().get().addr()
+            }
+
+            #[inline(always)]
+            fn root() -> *mut u8 {
+                Self::storage().get()
+            }
+        }
+    };
+    ($(#[$meta:meta])* $vis:vis $name:ident: $offset:ty = $($rest:tt)*) => {
+        $crate::address_space!($(#[$meta])* $vis $name: $offset >> 0 = $($rest)*);
+    };
+    ($(#[$meta:meta])* $vis:vis $name:ident = $($rest:tt)*) => {
+        $crate::address_space!($(#[$meta])* $vis $name: u16 >> 0 = $($rest)*);
+    };
+}
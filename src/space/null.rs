@@ -0,0 +1,181 @@
+use core::marker::PhantomData;
+
+use crate::offset::{ConstOffset, Offset};
+
+/// Selects which offset, if any, is used for the null pointer
+///
+/// `NonNull` stores its offset in the [`NonNull`](NullEncoding::NonNull) type, which has a niche
+/// at the null offset where possible, so that `Option<NonNull<T, S>>` is as small as `NonNull`.
+pub trait NullEncoding<O: Offset> {
+    /// The representation of a non-null offset
+    type NonNull: Copy;
+
+    /// The null offset as an integer, usable in constant expressions
+    const NULL: Option<usize>;
+
+    /// Returns the null offset, or `None` if the address space has no null pointer
+    fn null() -> Option<O>;
+    fn is_null(offset: O) -> bool;
+    fn to_non_null(offset: O) -> Option<Self::NonNull>;
+    /// Converts the offset to its non-null representation
+    ///
+    /// # Safety
+    /// The offset must not be the null offset
+    unsafe fn to_non_null_unchecked(offset: O) -> Self::NonNull;
+    fn from_non_null(offset: Self::NonNull) -> O;
+}
+
+/// A null encoding that can convert the offset `V` to its non-null representation at compile
+/// time
+///
+/// `V` is the offset xor'd with the null offset, see [`non_null_const`](super::non_null_const).
+/// Using [`NON_NULL`](ConstNullEncoding::NON_NULL) fails to compile if the offset is the null
+/// offset.
+pub trait ConstNullEncoding<O: ConstOffset<V>, const V: usize>: NullEncoding<O> {
+    const NON_NULL: Self::NonNull;
+}
+
+/// Uses the offset `NULL` as the null pointer
+///
+/// Offsets are stored xor'd with `NULL` in `NonNull`, so that the niche of the null pointer is
+/// kept. The default is `Sentinel<0>`, which means that the first byte of the arena can't be
+/// pointed to.
+///
+/// `NULL` has to fit into the offset type, otherwise using the address space fails to compile:
+///
+/// ```compile_fail
+/// use tinyptr::{ptr::MutPtr, space::{AddressSpace, Sentinel}};
+///
+/// struct Heap;
+///
+/// impl AddressSpace for Heap {
+///     type Offset = u8;
+///     type Null = Sentinel<256>;
+///
+///     fn base() -> usize {
+///         0x2000_0000
+///     }
+/// }
+///
+/// let ptr = MutPtr::<u8, Heap>::new(core::ptr::null_mut());
+/// ```
+pub struct Sentinel<const NULL: usize>;
+
+/// Checks at compile time that the null offset of a [`Sentinel`] fits into `O`
+struct Fits<O, const NULL: usize>(PhantomData<O>);
+
+impl<O: Offset, const NULL: usize> Fits<O, NULL> {
+    const NULL: usize = {
+        assert!(
+            O::BITS >= usize::BITS || NULL >> O::BITS == 0,
+            "the null offset doesn't fit into the offset type"
+        );
+        NULL
+    };
+}
+
+impl<O: Offset, const NULL: usize> NullEncoding<O> for Sentinel<NULL> {
+    type NonNull = O::NonZero;
+
+    const NULL: Option<usize> = Some(Fits::<O, NULL>::NULL);
+
+    #[inline(always)]
+    fn null() -> Option<O> {
+        Some(O::from_usize_truncate(Fits::<O, NULL>::NULL))
+    }
+    #[inline(always)]
+    fn is_null(offset: O) -> bool {
+        offset.to_usize() == Fits::<O, NULL>::NULL
+    }
+    #[inline(always)]
+    fn to_non_null(offset: O) -> Option<Self::NonNull> {
+        O::from_usize_truncate(offset.to_usize() ^ Fits::<O, NULL>::NULL).to_nonzero()
+    }
+    #[inline(always)]
+    unsafe fn to_non_null_unchecked(offset: O) -> Self::NonNull {
+        O::from_usize_truncate(offset.to_usize() ^ Fits::<O, NULL>::NULL).to_nonzero_unchecked()
+    }
+    #[inline(always)]
+    fn from_non_null(offset: Self::NonNull) -> O {
+        O::from_usize_truncate(O::from_nonzero(offset).to_usize() ^ Fits::<O, NULL>::NULL)
+    }
+}
+
+impl<O: ConstOffset<V>, const NULL: usize, const V: usize> ConstNullEncoding<O, V>
+    for Sentinel<NULL>
+{
+    const NON_NULL: Self::NonNull = O::NON_ZERO;
+}
+
+/// Uses the largest offset (for example `0xFFFF`) as the null pointer
+///
+/// This makes the first byte of the arena addressable, at the cost of the last one.
+pub struct MaxNull;
+
+impl<O: Offset> NullEncoding<O> for MaxNull {
+    type NonNull = O::NonZero;
+
+    const NULL: Option<usize> = Some(usize::MAX >> (usize::BITS - O::BITS));
+
+    #[inline(always)]
+    fn null() -> Option<O> {
+        Some(O::from_usize_truncate(usize::MAX))
+    }
+    #[inline(always)]
+    fn is_null(offset: O) -> bool {
+        offset == O::from_usize_truncate(usize::MAX)
+    }
+    #[inline(always)]
+    fn to_non_null(offset: O) -> Option<Self::NonNull> {
+        O::from_usize_truncate(!offset.to_usize()).to_nonzero()
+    }
+    #[inline(always)]
+    unsafe fn to_non_null_unchecked(offset: O) -> Self::NonNull {
+        O::from_usize_truncate(!offset.to_usize()).to_nonzero_unchecked()
+    }
+    #[inline(always)]
+    fn from_non_null(offset: Self::NonNull) -> O {
+        O::from_usize_truncate(!O::from_nonzero(offset).to_usize())
+    }
+}
+
+impl<O: ConstOffset<V>, const V: usize> ConstNullEncoding<O, V> for MaxNull {
+    const NON_NULL: Self::NonNull = O::NON_ZERO;
+}
+
+/// The address space has no null pointer, every offset is a valid address
+///
+/// Raw pointers can't be null in such an address space, and `Option<NonNull<T, S>>` is larger
+/// than `NonNull<T, S>`.
+pub struct NoNull;
+
+impl<O: Offset> NullEncoding<O> for NoNull {
+    type NonNull = O;
+
+    const NULL: Option<usize> = None;
+
+    #[inline(always)]
+    fn null() -> Option<O> {
+        None
+    }
+    #[inline(always)]
+    fn is_null(_: O) -> bool {
+        false
+    }
+    #[inline(always)]
+    fn to_non_null(offset: O) -> Option<Self::NonNull> {
+        Some(offset)
+    }
+    #[inline(always)]
+    unsafe fn to_non_null_unchecked(offset: O) -> Self::NonNull {
+        offset
+    }
+    #[inline(always)]
+    fn from_non_null(offset: Self::NonNull) -> O {
+        offset
+    }
+}
+
+impl<O: ConstOffset<V>, const V: usize> ConstNullEncoding<O, V> for NoNull {
+    const NON_NULL: Self::NonNull = O::OFFSET;
+}
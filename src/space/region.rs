@@ -0,0 +1,265 @@
+use core::marker::PhantomData;
+
+use crate::{offset::Offset, util::FullVTables};
+
+use super::{AddressSpace, NullEncoding, Sentinel};
+
+/// A contiguous block of memory in a [`MultiRegion`] address space
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub start: usize,
+    pub len: usize,
+}
+
+impl Region {
+    pub const fn new(start: usize, len: usize) -> Self {
+        Self { start, len }
+    }
+
+    pub const fn contains(&self, addr: usize) -> bool {
+        addr.wrapping_sub(self.start) < self.len
+    }
+}
+
+/// Describes the memory regions of a [`MultiRegion`] address space
+///
+/// ```
+/// # use tinyptr::space::{MultiRegion, Region, RegionTable};
+/// struct Stm32f4;
+///
+/// impl RegionTable for Stm32f4 {
+///     type Offset = u32;
+///     const REGIONS: &'static [Region] = &[
+///         Region::new(0x2000_0000, 112 * 1024), // SRAM1
+///         Region::new(0x2001_c000, 16 * 1024),  // SRAM2
+///         Region::new(0x1000_0000, 64 * 1024),  // CCM
+///     ];
+/// }
+///
+/// type Ram = MultiRegion<Stm32f4>;
+/// ```
+pub trait RegionTable {
+    /// The integer type that offsets are stored as
+    type Offset: Offset;
+    /// Number of bits the offsets inside of a region are shifted right by
+    const SHIFT: u32 = 0;
+    /// The regions in the address space. There has to be at least one.
+    const REGIONS: &'static [Region];
+    /// How trait object vtables are compressed, see [`VTableEncoding`](crate::util::VTableEncoding)
+    type VTables = FullVTables;
+    /// Which offset is used for the null pointer, see [`NullEncoding`]
+    ///
+    /// With the default `Sentinel<0>`, the first byte of the first region can't be pointed to.
+    type Null: NullEncoding<Self::Offset> = Sentinel<0>;
+}
+
+/// An address space made up of several non-contiguous regions
+///
+/// The upper bits of an offset select the region, the lower bits are the offset inside of it.
+/// Every region has to fit into the lower bits, which is checked at compile time.
+///
+/// Decoded pointers use the exposed provenance of their address, since the regions are separate
+/// allocations. Offsets with a region index that doesn't exist can't be decoded, they are
+/// rejected by [`try_decode`](AddressSpace::try_decode) and decode to the null address.
+pub struct MultiRegion<R>(PhantomData<R>);
+
+impl<R: RegionTable> MultiRegion<R> {
+    /// Number of offset bits used for the region index
+    pub const INDEX_BITS: u32 = {
+        assert!(!R::REGIONS.is_empty(), "address space has no regions");
+        usize::BITS - (R::REGIONS.len() - 1).leading_zeros()
+    };
+    /// Number of offset bits used for the offset inside of a region
+    pub const LOCAL_BITS: u32 = {
+        let bits = R::Offset::BITS - Self::INDEX_BITS;
+        let mut i = 0;
+        while i < R::REGIONS.len() {
+            let len = R::REGIONS[i].len;
+            assert!(len != 0, "empty region");
+            assert!(
+                bits >= usize::BITS || ((len - 1) >> R::SHIFT) >> bits == 0,
+                "region does not fit into the offset type"
+            );
+            i += 1;
+        }
+        bits
+    };
+    const LOCAL_MASK: usize = match 1usize.checked_shl(Self::LOCAL_BITS) {
+        Some(v) => v - 1,
+        None => usize::MAX,
+    };
+}
+
+impl<R: RegionTable> AddressSpace for MultiRegion<R> {
+    type Offset = R::Offset;
+    const SHIFT: u32 = R::SHIFT;
+    type VTables = R::VTables;
+    type Null = R::Null;
+
+    #[inline(always)]
+    fn base() -> usize {
+        R::REGIONS[0].start
+    }
+
+    #[inline]
+    fn encode(addr: usize) -> Option<Self::Offset> {
+        let (index, region) = R::REGIONS
+            .iter()
+            .enumerate()
+            .find(|(_, region)| region.contains(addr))?;
+        let local = addr - region.start;
+        if local & (Self::GRANULE - 1) != 0 {
+            return None;
+        }
+        let index = index.checked_shl(Self::LOCAL_BITS).unwrap_or(0);
+        Self::Offset::from_usize(index | (local >> Self::SHIFT))
+    }
+
+    #[inline]
+    unsafe fn encode_unchecked(addr: usize) -> Self::Offset {
+        // SAFE: the caller guarantees that the address is in one of the regions
+        Self::encode(addr).unwrap_unchecked()
+    }
+
+    #[inline]
+    fn decode(offset: Self::Offset) -> usize {
+        Self::try_decode(offset).unwrap_or(0)
+    }
+
+    #[inline]
+    fn try_decode(offset: Self::Offset) -> Option<usize> {
+        let offset = offset.to_usize();
+        let region = R::REGIONS.get(offset.checked_shr(Self::LOCAL_BITS).unwrap_or(0))?;
+        let local = (offset & Self::LOCAL_MASK) << Self::SHIFT;
+        if local >= region.len {
+            return None;
+        }
+        Some(region.start + local)
+    }
+
+    #[inline]
+    fn decode_ptr(offset: Self::Offset) -> *mut u8 {
+        core::ptr::with_exposed_provenance_mut(Self::decode(offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MultiRegion, Region, RegionTable};
+    use crate::{
+        ptr::MutPtr,
+        space::{AddressSpace, MaxNull},
+    };
+
+    struct Banks;
+
+    impl RegionTable for Banks {
+        type Offset = u16;
+        const REGIONS: &'static [Region] = &[
+            Region::new(0x1000, 0x100),
+            Region::new(0x4000, 0x80),
+            Region::new(0x8000, 0x10),
+        ];
+    }
+
+    type Ram = MultiRegion<Banks>;
+
+    #[test]
+    fn encodes_every_region() {
+        assert_eq!((Ram::INDEX_BITS, Ram::LOCAL_BITS), (2, 14));
+        assert_eq!(Ram::encode(0x1000), Some(0));
+        assert_eq!(Ram::encode(0x10ff), Some(0xff));
+        assert_eq!(Ram::encode(0x4000), Some(1 << 14));
+        assert_eq!(Ram::encode(0x407f), Some(1 << 14 | 0x7f));
+        assert_eq!(Ram::encode(0x800f), Some(2 << 14 | 0xf));
+        for addr in [0x1000, 0x10ff, 0x4000, 0x407f, 0x8000, 0x800f] {
+            assert_eq!(Ram::decode(Ram::encode(addr).unwrap()), addr);
+        }
+    }
+
+    #[test]
+    fn rejects_gaps() {
+        for addr in [0, 0xfff, 0x1100, 0x3fff, 0x4080, 0x7fff, 0x8010, usize::MAX] {
+            assert_eq!(Ram::encode(addr), None, "{addr:#x}");
+        }
+    }
+
+    #[test]
+    fn rejects_offsets_outside_of_the_regions() {
+        // Past the end of a region
+        assert_eq!(Ram::try_decode(0x100), None);
+        assert_eq!(Ram::try_decode(1 << 14 | 0x80), None);
+        assert_eq!(Ram::try_decode(2 << 14 | 0x10), None);
+        // Region index that doesn't exist
+        assert_eq!(Ram::try_decode(3 << 14), None);
+        assert_eq!(Ram::decode(3 << 14), 0);
+    }
+
+    #[test]
+    fn shifted() {
+        struct Words;
+
+        impl RegionTable for Words {
+            type Offset = u8;
+            const SHIFT: u32 = 2;
+            const REGIONS: &'static [Region] =
+                &[Region::new(0x1000, 0x100), Region::new(0x2000, 0x40)];
+        }
+
+        type Shifted = MultiRegion<Words>;
+        assert_eq!(Shifted::LOCAL_BITS, 7);
+        assert_eq!(Shifted::encode(0x10fc), Some(0x3f));
+        assert_eq!(Shifted::encode(0x203c), Some(0x80 | 0xf));
+        assert_eq!(Shifted::encode(0x2001), None);
+        assert_eq!(Shifted::decode(0x80 | 0xf), 0x203c);
+        assert_eq!(Shifted::try_decode(0x80 | 0x10), None);
+    }
+
+    #[test]
+    fn null_encoding() {
+        struct LastNull;
+
+        impl RegionTable for LastNull {
+            type Offset = u16;
+            type Null = MaxNull;
+            const REGIONS: &'static [Region] =
+                &[Region::new(0x1000, 0x100), Region::new(0x4000, 0x80)];
+        }
+
+        type Max = MultiRegion<LastNull>;
+        let first = core::ptr::without_provenance(0x1000);
+        assert_eq!(crate::ptr_to_offset::<Max>(first), Some(0));
+        assert_eq!(
+            crate::ptr_to_offset::<Max>(core::ptr::null()),
+            Some(u16::MAX)
+        );
+        assert!(crate::offset_to_ptr::<Max>(u16::MAX).is_null());
+        // The default can't point to the first byte
+        assert_eq!(crate::ptr_to_offset::<Ram>(first), None);
+    }
+
+    #[test]
+    fn vtables() {
+        trait Handler {}
+        impl Handler for u8 {}
+        impl Handler for u32 {}
+
+        crate::vtable_index! {
+            VTables {
+                dyn Handler: u8 = [u8, u32],
+            }
+        }
+
+        struct Indexed;
+
+        impl RegionTable for Indexed {
+            type Offset = u16;
+            type VTables = VTables;
+            const REGIONS: &'static [Region] = Banks::REGIONS;
+        }
+
+        type Small = MultiRegion<Indexed>;
+        // A u16 offset and a u8 vtable index, padded to the alignment of the offset
+        assert_eq!(core::mem::size_of::<MutPtr<dyn Handler, Small>>(), 4);
+    }
+}
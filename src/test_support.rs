@@ -0,0 +1,238 @@
+//! Address spaces for running code that uses tiny pointers on a host, enabled by the
+//! `test-support` feature
+//!
+//! Allocators on 64 bit hosts don't return addresses inside of a small fixed window, so tiny
+//! pointers can't point to them. Instead, [`host_space!`](crate::host_space) declares an address
+//! space whose arena is a static buffer, together with an allocator that serves from it:
+//!
+//! ```
+//! # #![feature(allocator_api)]
+//! use tinyptr::boxed::Box;
+//!
+//! tinyptr::host_space!(Heap: u16 = 4096);
+//!
+//! let b: Box<u32, _, Heap> = Box::new_in(42, Heap::arena());
+//! assert_eq!(*b, 42);
+//! ```
+//!
+//! Pointers derive their provenance from the buffer, so this also works under Miri with strict
+//! provenance checks:
+//!
+//! ```text
+//! MIRIFLAGS=-Zmiri-strict-provenance cargo miri test --all-features
+//! ```
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::UnsafeCell,
+    ptr::NonNull,
+};
+
+use crate::alloc_integration::lock::Lock;
+
+/// The buffer of a [`HostArena`], aligned so that shifted address spaces can use it
+#[repr(C, align(16))]
+struct Buffer<const SIZE: usize>([u8; SIZE]);
+
+struct State {
+    /// Offset of the first byte that hasn't been allocated
+    next: usize,
+    /// Number of live allocations
+    live: usize,
+}
+
+/// A static buffer used as the arena of a host address space
+///
+/// It implements [`Allocator`] as a bump allocator, memory is only reclaimed by
+/// [`reset`](HostArena::reset). Allocations are aligned to at least `GRANULE`, the granule of the
+/// address space, so that they can be encoded. The first granule is never allocated, so that no
+/// allocation is at the null offset of the default null encoding. The buffer is aligned to 16
+/// bytes, so address spaces can be shifted by up to 4 bits.
+pub struct HostArena<const SIZE: usize, const GRANULE: usize = 1> {
+    buf: UnsafeCell<Buffer<SIZE>>,
+    state: UnsafeCell<State>,
+    lock: Lock,
+}
+
+// SAFE: the buffer is only accessed through pointers handed out by the allocator, which never
+// overlap, and the state is only accessed while holding the lock
+unsafe impl<const SIZE: usize, const GRANULE: usize> Sync for HostArena<SIZE, GRANULE> {}
+
+impl<const SIZE: usize, const GRANULE: usize> HostArena<SIZE, GRANULE> {
+    pub const fn new() -> Self {
+        Self {
+            buf: UnsafeCell::new(Buffer([0; SIZE])),
+            state: UnsafeCell::new(State {
+                next: GRANULE,
+                live: 0,
+            }),
+            lock: Lock::new(),
+        }
+    }
+
+    /// Returns a pointer to the start of the buffer
+    pub fn root(&self) -> *mut u8 {
+        self.buf.get().cast()
+    }
+
+    /// Returns the number of bytes that have been allocated, including padding
+    pub fn used(&self) -> usize {
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held
+        unsafe { (*self.state.get()).next }
+    }
+
+    /// Returns the number of allocations that haven't been freed
+    pub fn live(&self) -> usize {
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held
+        unsafe { (*self.state.get()).live }
+    }
+
+    /// Frees all memory if every allocation has been deallocated
+    ///
+    /// Returns false and leaves the arena unchanged if there are live allocations.
+    pub fn reset(&self) -> bool {
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held
+        let state = unsafe { &mut *self.state.get() };
+        if state.live != 0 {
+            return false;
+        }
+        state.next = GRANULE;
+        true
+    }
+}
+
+impl<const SIZE: usize, const GRANULE: usize> Default for HostArena<SIZE, GRANULE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<const SIZE: usize, const GRANULE: usize> Allocator for &HostArena<SIZE, GRANULE> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let _guard = self.lock.lock();
+        // SAFE: the lock is held
+        let state = unsafe { &mut *self.state.get() };
+        let base = self.root().addr();
+        let align = layout.align().max(GRANULE);
+        let start = (base + state.next).next_multiple_of(align) - base;
+        let end = start.checked_add(layout.size()).ok_or(AllocError)?;
+        if end > SIZE {
+            return Err(AllocError);
+        }
+        state.next = end;
+        state.live += 1;
+        let ptr = self.root().wrapping_add(start);
+        // SAFE: the pointer is derived from the buffer, which is not null
+        let ptr = unsafe { NonNull::new_unchecked(ptr) };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+        let _guard = self.lock.lock();
+        (*self.state.get()).live -= 1;
+    }
+}
+
+/// Declares an address space backed by a static [`HostArena`]
+///
+/// ```
+/// tinyptr::host_space!(pub Heap: u16 = 4096);
+/// ```
+///
+/// The arena is returned by `Heap::arena()`, and can be used as an allocator. Like with
+/// [`address_space!`](crate::address_space), the offsets can be shifted, and the vtable and null
+/// pointer encodings can be set:
+///
+/// ```
+/// tinyptr::host_space!(pub Words: u8 >> 2 = 1024, null tinyptr::space::MaxNull);
+/// ```
+#[macro_export]
+macro_rules! host_space {
+    ($(#[$meta:meta])* $vis:vis $name:ident: $offset:ty >> $shift:literal = $size:expr $(, vtables $vtables:ty)? $(, null $null:ty)?) => {
+        $(#[$meta])*
+        $vis struct $name;
+
+        impl $name {
+            #[allow(dead_code)]
+            $vis fn arena() -> &'static $crate::test_support::HostArena<{ $size }, { 1 << $shift }> {
+                static ARENA: $crate::test_support::HostArena<{ $size }, { 1 << $shift }> =
+                    $crate::test_support::HostArena::new();
+                &ARENA
+            }
+        }
+
+        impl $crate::space::AddressSpace for $name {
+            type Offset = $offset;
+            const SHIFT: u32 = $shift;
+            $(type VTables = $vtables;)?
+            $(type Null = $null;)?
+
+            #[inline(always)]
+            fn base() -> usize {
+                Self::arena().root().addr()
+            }
+
+            #[inline(always)]
+            fn root() -> *mut u8 {
+                Self::arena().root()
+            }
+        }
+    };
+    ($(#[$meta:meta])* $vis:vis $name:ident: $offset:ty = $($rest:tt)*) => {
+        $crate::host_space!($(#[$meta])* $vis $name: $offset >> 0 = $($rest)*);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::{Allocator, Layout};
+
+    use crate::{
+        boxed::Box,
+        space::{AddressSpace, MaxNull, NullEncoding},
+    };
+
+    #[test]
+    fn reset() {
+        crate::host_space!(Heap: u16 = 64);
+        let a: Box<u64, _, Heap> = Box::new_in(1, Heap::arena());
+        assert!(!Heap::arena().reset());
+        assert_eq!(Heap::arena().live(), 1);
+        drop(a);
+        assert!(Heap::arena().reset());
+        assert_eq!(Heap::arena().used(), 1);
+    }
+
+    #[test]
+    fn allocate() {
+        crate::host_space!(Heap: u16 = 64);
+        let arena = Heap::arena();
+        let ptr = arena.allocate(Layout::new::<u16>()).unwrap().cast::<u8>();
+        assert_ne!(ptr.as_ptr(), Heap::root());
+        let ptr = arena
+            .allocate(Layout::from_size_align(8, 16).unwrap())
+            .unwrap();
+        assert_eq!(ptr.cast::<u8>().as_ptr().addr() % 16, 0);
+        assert!(arena.allocate(Layout::new::<[u8; 64]>()).is_err());
+        assert_eq!(arena.live(), 2);
+    }
+
+    #[test]
+    fn options() {
+        crate::host_space!(Words: u8 >> 2 = 1024, null MaxNull);
+        assert_eq!((Words::SHIFT, Words::GRANULE), (2, 4));
+        assert_eq!(Words::encode(Words::base() + 1020), Some(255));
+        assert_eq!(<Words as AddressSpace>::Null::null(), Some(255u8));
+    }
+
+    #[test]
+    fn allocate_shifted() {
+        crate::host_space!(Words: u8 >> 2 = 1024);
+        let a: Box<u8, _, Words> = Box::new_in(1, Words::arena());
+        let b: Box<u8, _, Words> = Box::new_in(2, Words::arena());
+        assert_eq!((*a, *b), (1, 2));
+        assert_eq!(Words::arena().used(), 9);
+    }
+}
@@ -1,4 +1,12 @@
-pub trait IntoTiny {
+use core::ptr::DynMetadata;
+
+use crate::{offset::Offset, space::AddressSpace};
+
+/// Compression of pointer metadata
+///
+/// The address space `S` determines the width of compressed slice lengths and how vtables are
+/// compressed.
+pub trait IntoTiny<S: ?Sized> {
     type Tiny: Copy;
     /// Converts the type into a smaller version, without range-checking.
     ///
@@ -9,7 +17,7 @@ pub trait IntoTiny {
     fn from_tiny(t: Self::Tiny) -> Self;
 }
 
-impl IntoTiny for () {
+impl<S: ?Sized> IntoTiny<S> for () {
     type Tiny = ();
 
     unsafe fn into_tiny_unchecked(self) -> Self::Tiny {
@@ -25,34 +33,135 @@ impl IntoTiny for () {
     }
 }
 
-impl IntoTiny for usize {
-    type Tiny = crate::TinyUSize;
+impl<S: AddressSpace> IntoTiny<S> for usize {
+    type Tiny = crate::TinyUSize<S>;
 
     unsafe fn into_tiny_unchecked(self) -> Self::Tiny {
-        self as Self::Tiny
+        Offset::from_usize_truncate(self)
     }
 
     fn into_tiny(self) -> Option<Self::Tiny> {
-        self.try_into().ok()
+        Offset::from_usize(self)
     }
 
     fn from_tiny(t: Self::Tiny) -> Self {
-        t as Self
+        t.to_usize()
     }
 }
 
-impl<T: ?Sized> IntoTiny for core::ptr::DynMetadata<T> {
-    type Tiny = Self;
+impl<T: ?Sized, S: AddressSpace> IntoTiny<S> for DynMetadata<T>
+where
+    S::VTables: VTableEncoding<T>,
+{
+    type Tiny = <S::VTables as VTableEncoding<T>>::Tiny;
 
     unsafe fn into_tiny_unchecked(self) -> Self::Tiny {
-        self
+        S::VTables::into_tiny_unchecked(self)
     }
 
     fn into_tiny(self) -> Option<Self::Tiny> {
-        Some(self)
+        S::VTables::into_tiny(self)
     }
 
     fn from_tiny(t: Self::Tiny) -> Self {
+        S::VTables::from_tiny(t)
+    }
+}
+
+/// Compression of trait object vtable pointers
+///
+/// The encoding is selected by [`AddressSpace::VTables`]. By default, the vtable pointers are
+/// stored uncompressed, see [`vtable_index!`](crate::vtable_index) for a compressed encoding.
+pub trait VTableEncoding<T: ?Sized> {
+    type Tiny: Copy;
+    /// Compresses the vtable pointer, without checking if it is known.
+    ///
+    /// # Safety
+    /// The caller has to ensure that the vtable can be represented in the compressed form.
+    unsafe fn into_tiny_unchecked(m: DynMetadata<T>) -> Self::Tiny;
+    fn into_tiny(m: DynMetadata<T>) -> Option<Self::Tiny>;
+    fn from_tiny(t: Self::Tiny) -> DynMetadata<T>;
+}
+
+/// Stores vtable pointers uncompressed
+pub struct FullVTables;
+
+impl<T: ?Sized> VTableEncoding<T> for FullVTables {
+    type Tiny = DynMetadata<T>;
+
+    unsafe fn into_tiny_unchecked(m: DynMetadata<T>) -> Self::Tiny {
+        m
+    }
+
+    fn into_tiny(m: DynMetadata<T>) -> Option<Self::Tiny> {
+        Some(m)
+    }
+
+    fn from_tiny(t: Self::Tiny) -> DynMetadata<T> {
         t
     }
 }
+
+/// Declares a table of vtables, which compresses trait object pointers to an index
+///
+/// ```
+/// # #![feature(ptr_metadata)]
+/// # use core::any::Any;
+/// # trait Handler {}
+/// # struct ButtonHandler;
+/// # struct TimerHandler;
+/// # impl Handler for ButtonHandler {}
+/// # impl Handler for TimerHandler {}
+/// tinyptr::vtable_index! {
+///     pub VTables {
+///         dyn Handler: u8 = [ButtonHandler, TimerHandler],
+///         dyn Any: u8 = [u32, ButtonHandler],
+///     }
+/// }
+///
+/// tinyptr::address_space!(pub Sram = extern _sram_start, vtables VTables);
+/// ```
+///
+/// With this, a `Box<dyn Handler, Global, Sram>` takes up 4 bytes. Creating a pointer to a type that
+/// is not part of the table fails.
+///
+/// Vtables are looked up by comparing their address with every entry of the table, so the
+/// table should be kept short. The compiler may emit duplicate vtables for the same type in
+/// different codegen units, so crates using this should be built with `codegen-units = 1` or
+/// LTO. A vtable that isn't found makes the checked constructors return `None`, and the
+/// unchecked ones panic. The calling crate needs to enable the `ptr_metadata` feature.
+#[macro_export]
+macro_rules! vtable_index {
+    ($(#[$meta:meta])* $vis:vis $name:ident { $($dyn:ty: $index:ty = [$($ty:ty),* $(,)?]),* $(,)? }) => {
+        $(#[$meta])*
+        $vis struct $name;
+
+        $(
+            impl $crate::util::VTableEncoding<$dyn> for $name {
+                type Tiny = $index;
+
+                unsafe fn into_tiny_unchecked(m: core::ptr::DynMetadata<$dyn>) -> Self::Tiny {
+                    match <Self as $crate::util::VTableEncoding<$dyn>>::into_tiny(m) {
+                        Some(index) => index,
+                        None => panic!(
+                            "vtable of `{}` not found in `{}`",
+                            stringify!($dyn),
+                            stringify!($name)
+                        ),
+                    }
+                }
+
+                fn into_tiny(m: core::ptr::DynMetadata<$dyn>) -> Option<Self::Tiny> {
+                    let vtables = [$(core::ptr::metadata(core::ptr::null::<$ty>() as *const $dyn)),*];
+                    let index = vtables.iter().position(|&vtable| vtable == m)?;
+                    $crate::offset::Offset::from_usize(index)
+                }
+
+                fn from_tiny(t: Self::Tiny) -> core::ptr::DynMetadata<$dyn> {
+                    let vtables = [$(core::ptr::metadata(core::ptr::null::<$ty>() as *const $dyn)),*];
+                    vtables[$crate::offset::Offset::to_usize(t)]
+                }
+            }
+        )*
+    };
+}
@@ -0,0 +1,277 @@
+//! Runtime validation of tiny pointers, enabled by the `validate` feature
+//!
+//! With the feature enabled, the unchecked constructors, `as_wide_ptr`, `offset` and
+//! `Box::from_raw_in` check the range, alignment and metadata of the pointers they are given, and
+//! report invalid ones to a hook that can be set with [`set_hook`].
+use core::{
+    fmt,
+    panic::Location,
+    ptr::Pointee,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::{
+    offset::Offset,
+    space::{AddressSpace, NullEncoding},
+    util::IntoTiny,
+};
+
+/// The reason a pointer failed validation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Violation {
+    /// The address is outside of the address space
+    OutOfRange { addr: usize },
+    /// The offset doesn't decode to an address in the address space
+    InvalidOffset { offset: usize },
+    /// The address is not aligned to `align` bytes
+    Misaligned { addr: usize, align: usize },
+    /// The pointer metadata can't be compressed
+    Metadata,
+    /// The pointer is null, or the address space has no null pointer
+    Null,
+    /// Pointer arithmetic left the address space
+    Overflow { addr: usize, count: isize },
+    /// The tag of a tagged pointer doesn't fit into its tag bits
+    Tag { tag: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::OutOfRange { addr } => write!(f, "address {addr:#x} is out of range"),
+            Self::InvalidOffset { offset } => write!(f, "offset {offset:#x} can't be decoded"),
+            Self::Misaligned { addr, align } => {
+                write!(f, "address {addr:#x} is not aligned to {align} bytes")
+            }
+            Self::Metadata => write!(f, "pointer metadata can't be compressed"),
+            Self::Null => write!(f, "invalid null pointer"),
+            Self::Overflow { addr, count } => {
+                write!(
+                    f,
+                    "offsetting {addr:#x} by {count} leaves the address space"
+                )
+            }
+            Self::Tag { tag } => write!(f, "tag {tag:#x} doesn't fit into the tag bits"),
+        }
+    }
+}
+
+/// A function that validation failures are reported to
+pub type Hook = fn(Violation, &'static Location<'static>);
+
+static HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Sets the function that validation failures are reported to
+///
+/// The default hook panics. If a custom hook returns, execution continues as if the check had
+/// passed.
+pub fn set_hook(hook: Hook) {
+    HOOK.store(hook as *mut (), Ordering::Release);
+}
+
+fn default_hook(violation: Violation, location: &'static Location<'static>) {
+    panic!("invalid tiny pointer at {location}: {violation}");
+}
+
+#[cold]
+#[track_caller]
+pub(crate) fn report(violation: Violation) {
+    let location = Location::caller();
+    let hook = HOOK.load(Ordering::Acquire);
+    if hook.is_null() {
+        default_hook(violation, location);
+    } else {
+        // SAFE: the pointer was created from a `Hook` in `set_hook`
+        let hook: Hook = unsafe { core::mem::transmute::<*mut (), Hook>(hook) };
+        hook(violation, location);
+    }
+}
+
+/// Checks that a pointer can be encoded in the address space, and is aligned for its pointee
+#[track_caller]
+pub(crate) fn check_ptr<T: ?Sized, S: AddressSpace>(ptr: *const T)
+where
+    <T as Pointee>::Metadata: IntoTiny<S>,
+{
+    if IntoTiny::<S>::into_tiny(core::ptr::metadata(ptr)).is_none() {
+        report(Violation::Metadata);
+    }
+    check_pointee::<T, S>(ptr);
+}
+
+/// Checks the address of a pointer like [`check_ptr`], without checking its metadata
+#[track_caller]
+pub(crate) fn check_pointee<T: ?Sized, S: AddressSpace>(ptr: *const T) {
+    let addr = ptr.cast::<()>();
+    if addr.is_null() {
+        if S::Null::null().is_none() {
+            report(Violation::Null);
+        }
+        return;
+    }
+    // SAFE: the metadata of a raw pointer always describes a valid value
+    let align = unsafe { core::mem::align_of_val_raw(ptr) };
+    check_addr::<S>(addr.addr(), align);
+}
+
+#[track_caller]
+fn check_addr<S: AddressSpace>(addr: usize, align: usize) {
+    match S::encode(addr) {
+        Some(offset) if S::Null::is_null(offset) => report(Violation::OutOfRange { addr }),
+        Some(_) if !addr.is_multiple_of(align) => report(Violation::Misaligned { addr, align }),
+        Some(_) => {}
+        None if !addr.wrapping_sub(S::base()).is_multiple_of(S::GRANULE) => {
+            report(Violation::Misaligned {
+                addr,
+                align: S::GRANULE,
+            })
+        }
+        None => report(Violation::OutOfRange { addr }),
+    }
+}
+
+/// Checks that an offset decodes to an address in the address space
+#[track_caller]
+pub(crate) fn check_offset<S: AddressSpace>(offset: S::Offset) {
+    if S::Null::is_null(offset) {
+        return;
+    }
+    match S::try_decode(offset) {
+        Some(addr) if S::encode(addr) != Some(offset) => report(Violation::OutOfRange { addr }),
+        Some(_) => {}
+        None => report(Violation::InvalidOffset {
+            offset: offset.to_usize(),
+        }),
+    }
+}
+
+/// Checks that pointer arithmetic stays in the address space
+#[track_caller]
+pub(crate) fn check_arithmetic<S: AddressSpace>(offset: S::Offset, count: isize, size: usize) {
+    if count != 0 && crate::checked_offset::<S>(offset, count, size).is_none() {
+        report(Violation::Overflow {
+            addr: S::decode(offset),
+            count,
+        });
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use core::{cell::RefCell, panic::Location, sync::atomic::Ordering};
+    use std::sync::{Mutex, PoisonError};
+
+    use super::{default_hook, set_hook, Violation, HOOK};
+    use crate::{ptr::MutPtr, space::AddressSpace};
+
+    crate::host_space!(Heap: u8 = 256);
+
+    /// An address space that only decodes the first 16 offsets
+    struct Short;
+
+    impl AddressSpace for Short {
+        type Offset = u8;
+
+        fn base() -> usize {
+            Heap::base()
+        }
+
+        fn root() -> *mut u8 {
+            Heap::root()
+        }
+
+        fn try_decode(offset: u8) -> Option<usize> {
+            (offset < 16).then(|| Self::decode(offset))
+        }
+    }
+
+    // The hook is global, so the tests that install it run one at a time
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    std::thread_local! {
+        static REPORTED: RefCell<Option<Vec<Violation>>> = const { RefCell::new(None) };
+    }
+
+    /// Records violations on threads that are inside of [`reported`]
+    fn record(violation: Violation, location: &'static Location<'static>) {
+        let recorded = REPORTED.with_borrow_mut(|reported| {
+            assert_eq!(location.file(), file!());
+            reported.as_mut().map(|reported| reported.push(violation))
+        });
+        if recorded.is_none() {
+            default_hook(violation, location);
+        }
+    }
+
+    /// Runs `f` with the recording hook, and returns the violations it reported
+    fn reported(f: impl FnOnce()) -> Vec<Violation> {
+        let _serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+        set_hook(record);
+        REPORTED.set(Some(Vec::new()));
+        f();
+        HOOK.store(core::ptr::null_mut(), Ordering::Release);
+        REPORTED.take().unwrap()
+    }
+
+    #[test]
+    fn valid() {
+        let ptr = Heap::root().wrapping_add(8);
+        assert_eq!(
+            reported(|| {
+                // SAFE: the pointer is inside of the address space
+                let _ = unsafe { MutPtr::<u8, Heap>::new_unchecked(ptr) };
+            }),
+            []
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        let ptr = Heap::root().wrapping_add(300);
+        assert_eq!(
+            reported(|| {
+                // SAFE: the violation is reported instead of encoding the pointer
+                let _ = unsafe { MutPtr::<u8, Heap>::new_unchecked(ptr) };
+            }),
+            [Violation::OutOfRange { addr: ptr.addr() }]
+        );
+    }
+
+    #[test]
+    fn misaligned() {
+        let ptr = Heap::root().wrapping_add(5).cast::<u32>();
+        assert_eq!(
+            reported(|| {
+                // SAFE: the violation is reported instead of encoding the pointer
+                let _ = unsafe { MutPtr::<u32, Heap>::new_unchecked(ptr) };
+            }),
+            [Violation::Misaligned {
+                addr: ptr.addr(),
+                align: 4
+            }]
+        );
+    }
+
+    #[test]
+    fn metadata() {
+        let ptr = core::ptr::slice_from_raw_parts_mut(Heap::root().wrapping_add(8), 300);
+        assert_eq!(
+            reported(|| {
+                // SAFE: the violation is reported instead of encoding the pointer
+                let _ = unsafe { MutPtr::<[u8], Heap>::new_unchecked(ptr) };
+            }),
+            [Violation::Metadata]
+        );
+    }
+
+    #[test]
+    fn invalid_offset() {
+        assert_eq!(
+            reported(|| {
+                let _ = MutPtr::<u8, Short>::from_raw_parts(200, ()).as_wide_ptr();
+            }),
+            [Violation::InvalidOffset { offset: 200 }]
+        );
+    }
+}